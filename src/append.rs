@@ -0,0 +1,174 @@
+use crate::curve::curve_basis_from;
+use crate::triangulate::{flatten_segments, triangulate_columns, SampleMode};
+use crate::{Line, Point, Triangle};
+use svgtypes::PathSegment;
+
+// area that grows one (x, y0, y1) sample at a time, only the trailing curve segments
+//   that a new sample changes are flattened again
+pub struct AppendableArea {
+    samples_per_segment: i32,
+    sample_mode: SampleMode,
+    top_points: Vec<Point>,
+    bot_points: Vec<Point>,
+    top_path: Vec<PathSegment>,
+    bot_path: Vec<PathSegment>,
+    // flattened columns of each path segment, aligned with the path so [0] (MoveTo) is empty
+    segment_columns: Vec<Vec<Line>>,
+    columns: Vec<Line>,
+    // two per pair of adjacent columns, so appending only ever rewrites the tail
+    triangles: Vec<Triangle>,
+    // first column that changed since the last call to mark_synced()
+    first_dirty_column: Option<usize>,
+}
+
+impl AppendableArea {
+    pub fn new(samples_per_segment: i32, sample_mode: SampleMode) -> Result<Self, String> {
+        if samples_per_segment <= 0 {
+            return Err("samples_per_segment must be positive".into());
+        }
+        Ok(Self {
+            samples_per_segment,
+            sample_mode,
+            top_points: Vec::new(),
+            bot_points: Vec::new(),
+            top_path: Vec::new(),
+            bot_path: Vec::new(),
+            segment_columns: Vec::new(),
+            columns: Vec::new(),
+            triangles: Vec::new(),
+            first_dirty_column: None,
+        })
+    }
+
+    pub fn push(&mut self, x: f64, y0: f64, y1: f64) -> Result<(), String> {
+        if matches!(self.top_points.last(), Some(last) if x < last.x) {
+            return Err("Samples must be appended in increasing x order".into());
+        }
+        self.top_points.push(Point::new(x, y1));
+        self.bot_points.push(Point::new(x, y0));
+
+        // curves of fewer than 4 points change from their second segment on
+        let n_points = self.top_points.len();
+        let first_changed = if n_points < 4 { 1 } else { n_points - 1 };
+        let keep = first_changed.min(self.top_path.len());
+        self.top_path.truncate(keep);
+        self.bot_path.truncate(keep);
+        self.top_path
+            .extend(curve_basis_from(&self.top_points, keep));
+        self.bot_path
+            .extend(curve_basis_from(&self.bot_points, keep));
+        let (top_path, bot_path) = (&self.top_path, &self.bot_path);
+
+        let new_columns = flatten_segments(
+            top_path,
+            bot_path,
+            self.samples_per_segment,
+            self.sample_mode,
+            first_changed..top_path.len(),
+        )?;
+
+        if self.segment_columns.is_empty() {
+            self.segment_columns.push(Vec::new());
+        }
+        self.segment_columns.truncate(first_changed);
+        let first_column: usize = self.segment_columns.iter().map(Vec::len).sum();
+        self.columns.truncate(first_column);
+        self.columns.extend(new_columns.iter().flatten());
+        self.segment_columns.extend(new_columns);
+
+        let first_quad = first_column.saturating_sub(1);
+        self.triangles.truncate(first_quad * 2);
+        self.triangles
+            .extend(triangulate_columns(&self.columns[first_quad..]));
+        self.first_dirty_column = Some(
+            self.first_dirty_column
+                .map_or(first_column, |col| col.min(first_column)),
+        );
+        Ok(())
+    }
+
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+
+    // index of the first triangle that changed since the last call to mark_synced()
+    pub fn first_dirty_triangle(&self) -> Option<usize> {
+        self.first_dirty_column.map(|col| col.saturating_sub(1) * 2)
+    }
+
+    pub fn mark_synced(&mut self) {
+        self.first_dirty_column = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::curve_basis;
+    use crate::triangulate::flatten_area;
+
+    fn samples() -> Vec<(f64, f64, f64)> {
+        (0..12)
+            .map(|idx| {
+                let x = idx as f64 * 10.;
+                let y0 = 50. + (idx % 3) as f64 * 7.;
+                (x, y0, y0 - 5. - (idx % 4) as f64 * 3.)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn curve_basis_from_matches_the_tail_of_curve_basis() {
+        let points: Vec<Point> = samples()
+            .iter()
+            .map(|&(x, y, _)| Point::new(x, y))
+            .collect();
+        for n in 0..=points.len() {
+            let full = curve_basis(&points[..n]);
+            for first in 0..=full.len() {
+                assert_eq!(curve_basis_from(&points[..n], first), full[first..]);
+            }
+        }
+    }
+
+    #[test]
+    fn pushing_matches_building_the_area_in_one_go() {
+        for mode in [SampleMode::Uniform, SampleMode::ArcLength] {
+            let mut area = AppendableArea::new(4, mode).unwrap();
+            let mut top = Vec::new();
+            let mut bot = Vec::new();
+            for (x, y0, y1) in samples() {
+                area.push(x, y0, y1).unwrap();
+                top.push(Point::new(x, y1));
+                bot.push(Point::new(x, y0));
+                let columns =
+                    flatten_area(&curve_basis(&top), &curve_basis(&bot), 4, mode, None).unwrap();
+                assert_eq!(area.triangles(), triangulate_columns(&columns));
+            }
+        }
+    }
+
+    #[test]
+    fn only_the_tail_is_dirty_after_a_push() {
+        let mut area = AppendableArea::new(4, SampleMode::Uniform).unwrap();
+        for (x, y0, y1) in samples() {
+            area.push(x, y0, y1).unwrap();
+        }
+        area.mark_synced();
+        assert_eq!(area.first_dirty_triangle(), None);
+
+        let before = area.triangles().to_vec();
+        area.push(200., 40., 30.).unwrap();
+        let first_dirty = area.first_dirty_triangle().unwrap();
+        assert!(first_dirty > 0);
+        assert_eq!(area.triangles()[..first_dirty], before[..first_dirty]);
+    }
+
+    #[test]
+    fn samples_going_back_are_rejected() {
+        let mut area = AppendableArea::new(4, SampleMode::Uniform).unwrap();
+        area.push(10., 0., 1.).unwrap();
+        assert!(area.push(5., 0., 1.).is_err());
+        assert!(AppendableArea::new(0, SampleMode::Uniform).is_err());
+    }
+}
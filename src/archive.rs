@@ -0,0 +1,152 @@
+use crate::import::is_apple_music_csv;
+use crate::parse::{parse_history, ParsedHistory};
+use serde::Serialize;
+use std::io::{Cursor, Read};
+use zip::ZipArchive;
+
+// enough for the header row of apple's play activity csv
+const PREFIX_LEN: u64 = 8 * 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryFile {
+    // path inside the archive
+    pub name: String,
+    #[serde(flatten)]
+    pub parsed: ParsedHistory,
+}
+
+// an entry that looked like a history but couldn't be read or parsed, e.g. a truncated
+//   endsong_N.json or some other json array in a takeout
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedEntry {
+    pub name: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HistoryArchive {
+    pub files: Vec<HistoryFile>,
+    // one bad entry doesn't keep the rest of the archive from being read
+    pub failed: Vec<FailedEntry>,
+}
+
+// every history file in a zip (my_spotify_data.zip, a google takeout), at any depth
+// files are recognized by their contents, everything else in the archive is ignored
+// only .json and .csv entries are opened, and only the ones whose start looks like a history
+//   are read all the way, takeouts are mostly photos and videos
+// only a zip that can't be opened at all is an error
+pub fn read_history_zip(bytes: &[u8]) -> Result<HistoryArchive, String> {
+    let mut archive =
+        ZipArchive::new(Cursor::new(bytes)).map_err(|e| format!("Invalid zip file: {}", e))?;
+
+    let mut history = HistoryArchive::default();
+    let mut contents: Vec<u8> = Vec::new();
+    for idx in 0..archive.len() {
+        let mut entry = archive
+            .by_index(idx)
+            .map_err(|e| format!("Invalid zip file: {}", e))?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = entry.name().to_string();
+        if !has_history_extension(&name) {
+            continue;
+        }
+
+        contents.clear();
+        if let Err(e) = (&mut entry).take(PREFIX_LEN).read_to_end(&mut contents) {
+            let error = format!("Couldn't read it from the zip file: {}", e);
+            history.failed.push(FailedEntry { name, error });
+            continue;
+        }
+        if !could_be_history(&contents) {
+            continue;
+        }
+        if let Err(e) = entry.read_to_end(&mut contents) {
+            let error = format!("Couldn't read it from the zip file: {}", e);
+            history.failed.push(FailedEntry { name, error });
+            continue;
+        }
+        match parse_history(&contents) {
+            Ok(Some(parsed)) => history.files.push(HistoryFile { name, parsed }),
+            Ok(None) => {}
+            Err(error) => history.failed.push(FailedEntry { name, error }),
+        }
+    }
+    Ok(history)
+}
+
+fn has_history_extension(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.ends_with(".json") || name.ends_with(".csv")
+}
+
+// json histories are arrays, csvs have to have apple's header, sniff_format() has the final say
+fn could_be_history(prefix: &[u8]) -> bool {
+    let first = prefix.iter().find(|b| !b.is_ascii_whitespace());
+    first == Some(&b'[') || is_apple_music_csv(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::HistoryFormat;
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
+    use zip::CompressionMethod;
+
+    fn zip(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, contents) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    const REGULAR: &str =
+        r#"[{"endTime":"2021-03-01 12:34","msPlayed":60000,"trackName":"t","artistName":"a"}]"#;
+
+    #[test]
+    fn history_files_are_found_by_their_contents_at_any_depth() {
+        let bytes = zip(&[
+            ("Takeout/photos/cat.jpg", "not a history"),
+            ("Takeout/Spotify/MyData/renamed.json", REGULAR),
+            ("Takeout/notes.json", r#"{"not":"an array"}"#),
+            ("Takeout/other.json", r#"[{"some":"thing else"}]"#),
+        ]);
+        let history = read_history_zip(&bytes).unwrap();
+        assert_eq!(history.files.len(), 1);
+        assert_eq!(history.files[0].name, "Takeout/Spotify/MyData/renamed.json");
+        assert_eq!(
+            history.files[0].parsed.report.format,
+            HistoryFormat::Regular
+        );
+        assert_eq!(history.files[0].parsed.records.len(), 1);
+        assert!(history.failed.is_empty());
+    }
+
+    #[test]
+    fn a_corrupt_entry_doesnt_lose_the_rest() {
+        // cut off in the middle of its second row
+        let truncated = format!("{},{{\"endTime\":\"2021-03", &REGULAR[..REGULAR.len() - 1]);
+        let bytes = zip(&[
+            ("MyData/endsong_0.json", &truncated),
+            ("MyData/StreamingHistory0.json", REGULAR),
+        ]);
+        let history = read_history_zip(&bytes).unwrap();
+        assert_eq!(history.files.len(), 1);
+        assert_eq!(history.files[0].name, "MyData/StreamingHistory0.json");
+        assert_eq!(history.failed.len(), 1);
+        assert_eq!(history.failed[0].name, "MyData/endsong_0.json");
+        assert!(history.failed[0]
+            .error
+            .contains("Invalid streaming history"));
+    }
+
+    #[test]
+    fn not_a_zip() {
+        assert!(read_history_zip(REGULAR.as_bytes()).is_err());
+    }
+}
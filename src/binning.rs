@@ -0,0 +1,663 @@
+use crate::zone::Zone;
+use chrono::{Datelike, Duration, Months, NaiveDate, NaiveDateTime, Timelike};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::prelude::*;
+
+const HOUR: i64 = 60 * 60 * 1000;
+
+#[derive(Debug, Clone)]
+pub struct DataPoint {
+    pub key: String,
+    pub score: f64,
+    // ms since unix epoch
+    pub timestamp: i64,
+    // when set, only the first point with each distinct value counts towards a key's bin,
+    //   e.g. the track name for counting unique tracks
+    pub distinct: Option<String>,
+}
+
+impl DataPoint {
+    pub fn new(key: impl Into<String>, score: f64, timestamp: i64) -> Self {
+        Self {
+            key: key.into(),
+            score,
+            timestamp,
+            distinct: None,
+        }
+    }
+
+    pub fn with_distinct(mut self, distinct: impl Into<String>) -> Self {
+        self.distinct = Some(distinct.into());
+        self
+    }
+}
+
+// bins follow the calendar in the listener's time zone, so months/quarters/years have varying
+//   lengths, and so do days around daylight saving changes
+// hours are always an hour long, the hour repeated when clocks go back is two bins
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TimeStep {
+    Hour,
+    Day,
+    // weeks start on monday
+    IsoWeek,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl TimeStep {
+    // start of the bin containing `dt`
+    pub fn floor(&self, dt: NaiveDateTime) -> NaiveDateTime {
+        let date = dt.date();
+        let start = match self {
+            Self::Hour => return date.and_hms_opt(dt.hour(), 0, 0).unwrap(),
+            Self::Day => date,
+            Self::IsoWeek => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Self::Month => NaiveDate::from_ymd_opt(date.year(), date.month(), 1).unwrap(),
+            Self::Quarter => {
+                let first_month = (date.month0() / 3) * 3 + 1;
+                NaiveDate::from_ymd_opt(date.year(), first_month, 1).unwrap()
+            }
+            Self::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap(),
+        };
+        start.and_hms_opt(0, 0, 0).unwrap()
+    }
+
+    // start of the bin after the one beginning at `start`
+    pub fn next(&self, start: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self {
+            Self::Hour => start.checked_add_signed(Duration::hours(1)),
+            Self::Day => start.checked_add_signed(Duration::days(1)),
+            Self::IsoWeek => start.checked_add_signed(Duration::weeks(1)),
+            Self::Month => start.checked_add_months(Months::new(1)),
+            Self::Quarter => start.checked_add_months(Months::new(3)),
+            Self::Year => start.checked_add_months(Months::new(12)),
+        }
+    }
+}
+
+// dense per-key scores, every key has a value (possibly 0) in every bin
+#[derive(Debug, Clone)]
+pub struct BinnedSeries {
+    pub step: TimeStep,
+    pub zone: Zone,
+    // start of each bin in ms since epoch, followed by the end of the last bin
+    //   bin i covers [bin_edges[i], bin_edges[i + 1])
+    pub bin_edges: Vec<i64>,
+    // in order of first appearance
+    pub keys: Vec<String>,
+    // scores[key_idx][bin_idx]
+    pub scores: Vec<Vec<f64>>,
+    // keys summed into the other series by fold_others, in order of first appearance
+    pub folded: Vec<String>,
+}
+
+// name of the series fold_others makes, unless a kept key already has it
+pub const OTHER_KEY: &str = "Other";
+
+// which keys keep their own series when the rest are folded into one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TopKeys {
+    // the n keys with the highest total
+    Total(usize),
+    // the n keys with the highest score in any single bin
+    Peak(usize),
+    // every key that's in the top k of at least one bin
+    AnyBin(usize),
+}
+
+impl BinnedSeries {
+    pub fn n_bins(&self) -> usize {
+        self.bin_edges.len().saturating_sub(1)
+    }
+
+    pub fn bin_start(&self, bin_idx: usize) -> i64 {
+        self.bin_edges[bin_idx]
+    }
+
+    pub fn bin_end(&self, bin_idx: usize) -> i64 {
+        self.bin_edges[bin_idx + 1]
+    }
+
+    pub fn key_index(&self, key: &str) -> Option<usize> {
+        self.keys.iter().position(|k| k == key)
+    }
+
+    pub fn series(&self, key: &str) -> Option<&[f64]> {
+        self.key_index(key).map(|idx| self.scores[idx].as_slice())
+    }
+
+    // bin containing `timestamp`, which has to be inside the binned range
+    pub fn bin_index(&self, timestamp: i64) -> usize {
+        // edges are strictly increasing, and the first edge is <= every timestamp
+        self.bin_edges.partition_point(|&edge| edge <= timestamp) - 1
+    }
+
+    // adds every point to its key's bin, keys that aren't there yet are added at the end
+    // distinct values are only tracked within `sorted_points`, so it has to hold every point
+    //   of the bins it touches
+    fn accumulate(&mut self, sorted_points: &[&DataPoint]) {
+        let n_bins = self.n_bins();
+        let mut key_indices: HashMap<String, usize> = self
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(idx, key)| (key.clone(), idx))
+            .collect();
+        let mut seen: HashSet<(usize, usize, &str)> = HashSet::new();
+        for point in sorted_points {
+            let key_idx = match key_indices.get(&point.key) {
+                Some(&idx) => idx,
+                None => {
+                    self.keys.push(point.key.clone());
+                    self.scores.push(vec![0.; n_bins]);
+                    key_indices.insert(point.key.clone(), self.keys.len() - 1);
+                    self.keys.len() - 1
+                }
+            };
+            let bin_idx = self.bin_index(point.timestamp);
+            if let Some(distinct) = &point.distinct {
+                if !seen.insert((key_idx, bin_idx, distinct)) {
+                    continue;
+                }
+            }
+            self.scores[key_idx][bin_idx] += point.score;
+        }
+    }
+
+    // adds `new_points` to a series made by bin_points() without them, growing the bin range
+    //   if needed and recomputing only the bins they land in
+    // `all_points` is every point including the new ones, sorted by timestamp
+    // returns the (post-growth) indices of the recomputed bins, keys only ever get appended
+    pub fn add_points(
+        &mut self,
+        new_points: &[DataPoint],
+        all_points: &[DataPoint],
+    ) -> Result<Vec<usize>, String> {
+        if !self.folded.is_empty() {
+            return Err("Can't add points to a series with folded keys".into());
+        }
+        let first = match new_points.iter().map(|p| p.timestamp).min() {
+            Some(first) => first,
+            None => return Ok(Vec::new()),
+        };
+        let last = new_points.iter().map(|p| p.timestamp).max().unwrap();
+
+        // edges line up with the calendar, so the new ones meet the old ones exactly
+        if self.bin_edges.is_empty() {
+            self.bin_edges = bin_edges(first, last, self.step, self.zone)?;
+        } else {
+            let (old_first, old_last) = (self.bin_edges[0], *self.bin_edges.last().unwrap());
+            if first < old_first {
+                let mut before = bin_edges(first, old_first - 1, self.step, self.zone)?;
+                before.pop();
+                for series in self.scores.iter_mut() {
+                    series.splice(0..0, std::iter::repeat_n(0., before.len()));
+                }
+                self.bin_edges.splice(0..0, before);
+            }
+            if last >= old_last {
+                let after = bin_edges(old_last, last, self.step, self.zone)?;
+                self.bin_edges.extend_from_slice(&after[1..]);
+            }
+        }
+        let n_bins = self.n_bins();
+        for series in self.scores.iter_mut() {
+            series.resize(n_bins, 0.);
+        }
+
+        let mut bins: Vec<usize> = new_points
+            .iter()
+            .map(|p| self.bin_index(p.timestamp))
+            .collect();
+        bins.sort_unstable();
+        bins.dedup();
+
+        let mut in_bins: Vec<&DataPoint> = Vec::new();
+        for &bin in &bins {
+            for series in self.scores.iter_mut() {
+                series[bin] = 0.;
+            }
+            let (start, end) = (self.bin_start(bin), self.bin_end(bin));
+            let lo = all_points.partition_point(|p| p.timestamp < start);
+            let hi = all_points.partition_point(|p| p.timestamp < end);
+            in_bins.extend(&all_points[lo..hi]);
+        }
+        self.accumulate(&in_bins);
+        Ok(bins)
+    }
+
+    // every bin's scores as a percentage of that bin's total, empty bins stay at 0
+    pub fn to_share_of_bin(&mut self) {
+        for bin in 0..self.n_bins() {
+            let total: f64 = self.scores.iter().map(|series| series[bin]).sum();
+            if total != 0. {
+                for series in self.scores.iter_mut() {
+                    series[bin] *= 100. / total;
+                }
+            }
+        }
+    }
+
+    // index of the other series, always the last one when there is one
+    // look the series up with this rather than by name, its name depends on the other keys
+    pub fn other_index(&self) -> Option<usize> {
+        match self.folded.is_empty() {
+            true => None,
+            false => Some(self.keys.len() - 1),
+        }
+    }
+
+    pub fn is_folded(&self, key: &str) -> bool {
+        self.folded.iter().any(|k| k == key)
+    }
+
+    // sums every key not picked by `top` into one other series at the end,
+    //   the kept keys stay in their original order
+    // the series is called OTHER_KEY, or "Other (2)", "Other (3)"... if a kept key is called that
+    // ties are broken by order of first appearance
+    pub fn fold_others(&mut self, top: TopKeys) {
+        let mut keep = vec![false; self.keys.len()];
+        match top {
+            TopKeys::Total(n) => {
+                for idx in highest(&self.scores, n, |series| series.iter().sum()) {
+                    keep[idx] = true;
+                }
+            }
+            TopKeys::Peak(n) => {
+                let peak =
+                    |series: &[f64]| series.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                for idx in highest(&self.scores, n, peak) {
+                    keep[idx] = true;
+                }
+            }
+            TopKeys::AnyBin(k) => {
+                for bin in 0..self.n_bins() {
+                    let in_bin = |series: &[f64]| series[bin];
+                    for idx in highest(&self.scores, k, in_bin) {
+                        if self.scores[idx][bin] > 0. {
+                            keep[idx] = true;
+                        }
+                    }
+                }
+            }
+        }
+        if keep.iter().all(|&kept| kept) {
+            return;
+        }
+
+        let mut other = vec![0.; self.n_bins()];
+        let keys = std::mem::take(&mut self.keys);
+        let scores = std::mem::take(&mut self.scores);
+        for ((key, series), kept) in keys.into_iter().zip(scores).zip(keep) {
+            if kept {
+                self.keys.push(key);
+                self.scores.push(series);
+            } else {
+                for (total, score) in other.iter_mut().zip(series) {
+                    *total += score;
+                }
+                self.folded.push(key);
+            }
+        }
+        let other_key = std::iter::once(OTHER_KEY.to_string())
+            .chain((2..).map(|n| format!("{} ({})", OTHER_KEY, n)))
+            .find(|key| !self.keys.contains(key))
+            .unwrap();
+        self.keys.push(other_key);
+        self.scores.push(other);
+    }
+}
+
+pub fn bin_points(
+    points: &[DataPoint],
+    step: TimeStep,
+    zone: Zone,
+) -> Result<BinnedSeries, String> {
+    let mut sorted: Vec<&DataPoint> = points.iter().collect();
+    sorted.sort_by_key(|p| p.timestamp);
+
+    let mut binned = BinnedSeries {
+        step,
+        zone,
+        bin_edges: Vec::new(),
+        keys: Vec::new(),
+        scores: Vec::new(),
+        folded: Vec::new(),
+    };
+    if let (Some(first), Some(last)) = (sorted.first(), sorted.last()) {
+        binned.bin_edges = bin_edges(first.timestamp, last.timestamp, step, zone)?;
+        binned.accumulate(&sorted);
+    }
+    Ok(binned)
+}
+
+// indices of the `n` series with the highest `rank`, stable so ties go to the earlier series
+fn highest(scores: &[Vec<f64>], n: usize, rank: impl Fn(&[f64]) -> f64) -> Vec<usize> {
+    let ranks: Vec<f64> = scores.iter().map(|series| rank(series)).collect();
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|&a, &b| ranks[b].total_cmp(&ranks[a]));
+    order.truncate(n);
+    order
+}
+
+// edges of every bin from the one containing `first` up to and including the one containing `last`
+// a day or longer bin that starts in a skipped hour (clocks going forward) is merged into the
+//   one before it
+pub fn bin_edges(first: i64, last: i64, step: TimeStep, zone: Zone) -> Result<Vec<i64>, String> {
+    if step == TimeStep::Hour {
+        return hour_edges(first, last, zone);
+    }
+    let mut edge = step.floor(zone.to_local(first)?);
+    let mut edges = vec![zone.from_local(edge)?];
+    while *edges.last().unwrap() <= last {
+        edge = step.next(edge).ok_or("Timestamp out of range")?;
+        let edge_ts = zone.from_local(edge)?;
+        if edge_ts > *edges.last().unwrap() {
+            edges.push(edge_ts);
+        }
+    }
+    Ok(edges)
+}
+
+// hours are stepped through in utc, going by the wall clock would give the first of two
+//   repeated hours both of them
+fn hour_edges(first: i64, last: i64, zone: Zone) -> Result<Vec<i64>, String> {
+    // last time the wall clock showed a whole hour, at the offset in use at `timestamp`
+    let floor = |timestamp: i64| -> Result<i64, String> {
+        let local = zone.to_local(timestamp)?;
+        Ok(timestamp - (local - TimeStep::Hour.floor(local)).num_milliseconds())
+    };
+    let mut edges = vec![floor(first)?];
+    while *edges.last().unwrap() <= last {
+        // more than the last edge, the floor is under an hour before its argument
+        let next = edges
+            .last()
+            .unwrap()
+            .checked_add(HOUR)
+            .ok_or("Timestamp out of range")?;
+        edges.push(floor(next)?);
+    }
+    Ok(edges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> i64 {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp_millis()
+    }
+
+    fn lengths_in_minutes(edges: &[i64]) -> Vec<i64> {
+        edges.windows(2).map(|w| (w[1] - w[0]) / 60_000).collect()
+    }
+
+    fn local_hours(edges: &[i64], zone: Zone) -> Vec<u32> {
+        edges
+            .iter()
+            .map(|&edge| zone.to_local(edge).unwrap().hour())
+            .collect()
+    }
+
+    #[test]
+    fn repeated_hour_gets_its_own_bin() {
+        // clocks go back from 03:00 CEST to 02:00 CET at 01:00 utc
+        let berlin = Zone::parse("Europe/Berlin").unwrap();
+        let edges = bin_edges(
+            utc(2021, 10, 30, 22, 0),
+            utc(2021, 10, 31, 3, 30),
+            TimeStep::Hour,
+            berlin,
+        )
+        .unwrap();
+        assert_eq!(lengths_in_minutes(&edges), vec![60; 6]);
+        assert_eq!(local_hours(&edges, berlin), vec![0, 1, 2, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn skipped_hour_has_no_bin() {
+        // clocks go forward from 02:00 CET to 03:00 CEST at 01:00 utc
+        let berlin = Zone::parse("Europe/Berlin").unwrap();
+        let edges = bin_edges(
+            utc(2021, 3, 27, 23, 0),
+            utc(2021, 3, 28, 2, 30),
+            TimeStep::Hour,
+            berlin,
+        )
+        .unwrap();
+        assert_eq!(lengths_in_minutes(&edges), vec![60; 4]);
+        assert_eq!(local_hours(&edges, berlin), vec![0, 1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn hours_follow_half_hour_offsets() {
+        let kolkata = Zone::parse("Asia/Kolkata").unwrap();
+        let edges = bin_edges(
+            utc(2021, 1, 1, 0, 10),
+            utc(2021, 1, 1, 1, 40),
+            TimeStep::Hour,
+            kolkata,
+        )
+        .unwrap();
+        assert_eq!(
+            edges,
+            vec![
+                utc(2020, 12, 31, 23, 30),
+                utc(2021, 1, 1, 0, 30),
+                utc(2021, 1, 1, 1, 30),
+                utc(2021, 1, 1, 2, 30),
+            ]
+        );
+    }
+
+    #[test]
+    fn days_stretch_over_daylight_saving_changes() {
+        let berlin = Zone::parse("Europe/Berlin").unwrap();
+        let edges = bin_edges(
+            utc(2021, 10, 30, 12, 0),
+            utc(2021, 10, 31, 12, 0),
+            TimeStep::Day,
+            berlin,
+        )
+        .unwrap();
+        assert_eq!(lengths_in_minutes(&edges), vec![24 * 60, 25 * 60]);
+        let edges = bin_edges(
+            utc(2021, 3, 28, 12, 0),
+            utc(2021, 3, 28, 12, 0),
+            TimeStep::Day,
+            berlin,
+        )
+        .unwrap();
+        assert_eq!(lengths_in_minutes(&edges), vec![23 * 60]);
+    }
+
+    #[test]
+    fn points_land_in_the_repeated_hour_they_were_played_in() {
+        let berlin = Zone::parse("Europe/Berlin").unwrap();
+        let points = vec![
+            // 02:30 CEST and 02:30 CET
+            DataPoint::new("a", 1., utc(2021, 10, 31, 0, 30)),
+            DataPoint::new("a", 2., utc(2021, 10, 31, 1, 30)),
+        ];
+        let binned = bin_points(&points, TimeStep::Hour, berlin).unwrap();
+        assert_eq!(binned.scores, vec![vec![1., 2.]]);
+    }
+
+    fn days(edges: &[i64]) -> Vec<i64> {
+        lengths_in_minutes(edges)
+            .iter()
+            .map(|&minutes| minutes / (24 * 60))
+            .collect()
+    }
+
+    #[test]
+    fn months_follow_the_calendar() {
+        let edges = bin_edges(
+            utc(2020, 1, 15, 8, 0),
+            utc(2020, 3, 2, 8, 0),
+            TimeStep::Month,
+            Zone::Utc,
+        )
+        .unwrap();
+        assert_eq!(
+            edges,
+            vec![
+                utc(2020, 1, 1, 0, 0),
+                utc(2020, 2, 1, 0, 0),
+                utc(2020, 3, 1, 0, 0),
+                utc(2020, 4, 1, 0, 0),
+            ]
+        );
+        // a leap year
+        assert_eq!(days(&edges), vec![31, 29, 31]);
+
+        // over new year, in local time
+        let berlin = Zone::parse("Europe/Berlin").unwrap();
+        let edges = bin_edges(
+            utc(2020, 12, 31, 23, 30),
+            utc(2021, 1, 1, 0, 0),
+            TimeStep::Month,
+            berlin,
+        )
+        .unwrap();
+        assert_eq!(
+            edges,
+            vec![utc(2020, 12, 31, 23, 0), utc(2021, 1, 31, 23, 0)]
+        );
+        // march is an hour short in berlin
+        let edges = bin_edges(
+            utc(2021, 3, 10, 0, 0),
+            utc(2021, 3, 10, 0, 0),
+            TimeStep::Month,
+            berlin,
+        )
+        .unwrap();
+        assert_eq!(lengths_in_minutes(&edges), vec![31 * 24 * 60 - 60]);
+    }
+
+    #[test]
+    fn quarters_and_years() {
+        let edges = bin_edges(
+            utc(2021, 2, 1, 0, 0),
+            utc(2021, 7, 1, 0, 0),
+            TimeStep::Quarter,
+            Zone::Utc,
+        )
+        .unwrap();
+        assert_eq!(
+            edges,
+            vec![
+                utc(2021, 1, 1, 0, 0),
+                utc(2021, 4, 1, 0, 0),
+                utc(2021, 7, 1, 0, 0),
+                utc(2021, 10, 1, 0, 0),
+            ]
+        );
+        let edges = bin_edges(
+            utc(2019, 6, 1, 0, 0),
+            utc(2020, 6, 1, 0, 0),
+            TimeStep::Year,
+            Zone::Utc,
+        )
+        .unwrap();
+        assert_eq!(days(&edges), vec![365, 366]);
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        // 2021-01-01 is a friday, its week starts in 2020
+        let edges = bin_edges(
+            utc(2021, 1, 1, 12, 0),
+            utc(2021, 1, 4, 0, 0),
+            TimeStep::IsoWeek,
+            Zone::Utc,
+        )
+        .unwrap();
+        assert_eq!(
+            edges,
+            vec![
+                utc(2020, 12, 28, 0, 0),
+                utc(2021, 1, 4, 0, 0),
+                utc(2021, 1, 11, 0, 0),
+            ]
+        );
+
+        // sunday night in new york is monday in utc
+        let new_york = Zone::parse("America/New_York").unwrap();
+        let edges = bin_edges(
+            utc(2021, 3, 15, 3, 0),
+            utc(2021, 3, 15, 3, 0),
+            TimeStep::IsoWeek,
+            new_york,
+        )
+        .unwrap();
+        // and that week has the hour the clocks skipped
+        assert_eq!(edges, vec![utc(2021, 3, 8, 5, 0), utc(2021, 3, 15, 4, 0)]);
+        assert_eq!(lengths_in_minutes(&edges), vec![7 * 24 * 60 - 60]);
+    }
+
+    #[test]
+    fn the_last_bin_runs_to_the_end_of_its_period() {
+        let points = vec![
+            DataPoint::new("a", 1., utc(2021, 1, 10, 0, 0)),
+            DataPoint::new("a", 2., utc(2021, 2, 3, 0, 0)),
+        ];
+        let binned = bin_points(&points, TimeStep::Month, Zone::Utc).unwrap();
+        assert_eq!(binned.n_bins(), 2);
+        assert_eq!(binned.bin_end(1), utc(2021, 3, 1, 0, 0));
+        assert_eq!(binned.scores, vec![vec![1., 2.]]);
+    }
+
+    #[test]
+    fn points_on_the_last_edge_get_a_bin_of_their_own() {
+        // the second point is on the end of january's bin, so it starts february's
+        let points = vec![
+            DataPoint::new("a", 1., utc(2021, 1, 1, 0, 0)),
+            DataPoint::new("b", 2., utc(2021, 2, 1, 0, 0)),
+        ];
+        let binned = bin_points(&points, TimeStep::Month, Zone::Utc).unwrap();
+        assert_eq!(
+            binned.bin_edges,
+            vec![
+                utc(2021, 1, 1, 0, 0),
+                utc(2021, 2, 1, 0, 0),
+                utc(2021, 3, 1, 0, 0),
+            ]
+        );
+        assert_eq!(binned.bin_index(utc(2021, 2, 1, 0, 0)), 1);
+        assert_eq!(binned.scores, vec![vec![1., 0.], vec![0., 2.]]);
+
+        // same when it comes in a later batch, right on the old last edge
+        let mut binned = bin_points(&points[..1], TimeStep::Month, Zone::Utc).unwrap();
+        assert_eq!(binned.n_bins(), 1);
+        let bins = binned.add_points(&points[1..], &points).unwrap();
+        assert_eq!(bins, vec![1]);
+        assert_eq!(binned.bin_edges.last(), Some(&utc(2021, 3, 1, 0, 0)));
+        assert_eq!(binned.scores, vec![vec![1., 0.], vec![0., 2.]]);
+    }
+
+    #[test]
+    fn added_points_before_the_first_bin() {
+        let points = vec![
+            DataPoint::new("a", 1., utc(2020, 11, 30, 23, 59)),
+            DataPoint::new("a", 2., utc(2021, 1, 5, 0, 0)),
+        ];
+        let mut binned = bin_points(&points[1..], TimeStep::Month, Zone::Utc).unwrap();
+        let bins = binned.add_points(&points[..1], &points).unwrap();
+        assert_eq!(bins, vec![0]);
+        let full = bin_points(&points, TimeStep::Month, Zone::Utc).unwrap();
+        assert_eq!(binned.bin_edges, full.bin_edges);
+        assert_eq!(binned.scores, vec![vec![1., 0., 2.]]);
+    }
+}
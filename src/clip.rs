@@ -0,0 +1,335 @@
+use crate::{Line, Point, Triangle};
+
+// axis aligned rectangle in area space, an infinite range disables clipping on that axis
+#[derive(Debug, Clone, Copy)]
+pub struct Bounds {
+    pub x_min: f64,
+    pub x_max: f64,
+    pub y_min: f64,
+    pub y_max: f64,
+}
+
+impl Bounds {
+    pub fn new(x_min: f64, x_max: f64, y_min: f64, y_max: f64) -> Self {
+        Self {
+            x_min,
+            x_max,
+            y_min,
+            y_max,
+        }
+    }
+
+    pub fn x_range(x_min: f64, x_max: f64) -> Self {
+        Self::new(x_min, x_max, f64::NEG_INFINITY, f64::INFINITY)
+    }
+
+    pub fn around(points: impl IntoIterator<Item = Point>) -> Self {
+        points.into_iter().fold(
+            Self::new(
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+            ),
+            |b, pt| {
+                Self::new(
+                    b.x_min.min(pt.x),
+                    b.x_max.max(pt.x),
+                    b.y_min.min(pt.y),
+                    b.y_max.max(pt.y),
+                )
+            },
+        )
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self::new(
+            self.x_min.min(other.x_min),
+            self.x_max.max(other.x_max),
+            self.y_min.min(other.y_min),
+            self.y_max.max(other.y_max),
+        )
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.x_min <= other.x_max
+            && other.x_min <= self.x_max
+            && self.y_min <= other.y_max
+            && other.y_min <= self.y_max
+    }
+
+    pub fn contains(&self, pt: Point) -> bool {
+        (self.x_min..=self.x_max).contains(&pt.x) && (self.y_min..=self.y_max).contains(&pt.y)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Edge {
+    Left(f64),
+    Right(f64),
+    Bottom(f64),
+    Top(f64),
+}
+
+impl Edge {
+    fn inside(&self, pt: Point) -> bool {
+        match *self {
+            Self::Left(x) => pt.x >= x,
+            Self::Right(x) => pt.x <= x,
+            Self::Bottom(y) => pt.y >= y,
+            Self::Top(y) => pt.y <= y,
+        }
+    }
+
+    // point where p1 -> p2 crosses the edge, snapped exactly onto it
+    fn intersect(&self, p1: Point, p2: Point) -> Point {
+        match *self {
+            Self::Left(x) | Self::Right(x) => {
+                let t = (x - p1.x) / (p2.x - p1.x);
+                Point::new(x, p1.y + (p2.y - p1.y) * t)
+            }
+            Self::Bottom(y) | Self::Top(y) => {
+                let t = (y - p1.y) / (p2.y - p1.y);
+                Point::new(p1.x + (p2.x - p1.x) * t, y)
+            }
+        }
+    }
+}
+
+fn edges(bounds: &Bounds) -> impl Iterator<Item = Edge> {
+    [
+        Edge::Left(bounds.x_min),
+        Edge::Right(bounds.x_max),
+        Edge::Bottom(bounds.y_min),
+        Edge::Top(bounds.y_max),
+    ]
+    .into_iter()
+    .filter(|edge| match *edge {
+        Edge::Left(v) | Edge::Right(v) | Edge::Bottom(v) | Edge::Top(v) => v.is_finite(),
+    })
+}
+
+// sutherland-hodgman, the clipped polygon is convex so it's fanned back into triangles
+//   with the same winding as the input
+pub fn clip_triangles(triangles: &[Triangle], bounds: &Bounds) -> Vec<Triangle> {
+    let mut out: Vec<Triangle> = Vec::with_capacity(triangles.len());
+    for tri in triangles {
+        if tri.iter().all(|&pt| bounds.contains(pt)) {
+            out.push(*tri);
+            continue;
+        }
+
+        let mut polygon: Vec<Point> = tri.to_vec();
+        for edge in edges(bounds) {
+            if polygon.is_empty() {
+                break;
+            }
+            let input = std::mem::take(&mut polygon);
+            let mut prev = *input.last().unwrap();
+            for &cur in input.iter() {
+                match (edge.inside(prev), edge.inside(cur)) {
+                    (true, true) => polygon.push(cur),
+                    (true, false) => polygon.push(edge.intersect(prev, cur)),
+                    (false, true) => {
+                        polygon.push(edge.intersect(prev, cur));
+                        polygon.push(cur);
+                    }
+                    (false, false) => (),
+                }
+                prev = cur;
+            }
+        }
+
+        if polygon.len() >= 3 {
+            out.extend(
+                polygon
+                    .windows(2)
+                    .skip(1)
+                    .map(|pts| [polygon[0], pts[0], pts[1]]),
+            );
+        }
+    }
+    out
+}
+
+// drops the columns (lines from the top of a sample to the bottom) that are entirely left or
+//   right of `bounds`, except the one next to it on each side so a mesh of what's left still
+//   reaches the edges, columns go left to right
+pub fn clip_columns(columns: &[Line], bounds: &Bounds) -> Vec<Line> {
+    let left_of = |col: &Line| col.iter().all(|pt| pt.x < bounds.x_min);
+    let right_of = |col: &Line| col.iter().all(|pt| pt.x > bounds.x_max);
+    let first = columns.iter().position(|col| !left_of(col));
+    let last = columns.iter().rposition(|col| !right_of(col));
+    match (first, last) {
+        (Some(first), Some(last)) if first <= last => {
+            columns[first.saturating_sub(1)..(last + 2).min(columns.len())].to_vec()
+        }
+        _ => Vec::new(),
+    }
+}
+
+// liang-barsky
+pub fn clip_lines(lines: &[Line], bounds: &Bounds) -> Vec<Line> {
+    lines
+        .iter()
+        .filter_map(|&[p1, p2]| {
+            let (dx, dy) = (p2.x - p1.x, p2.y - p1.y);
+            let (mut t0, mut t1) = (0f64, 1f64);
+            for (p, q) in [
+                (-dx, p1.x - bounds.x_min),
+                (dx, bounds.x_max - p1.x),
+                (-dy, p1.y - bounds.y_min),
+                (dy, bounds.y_max - p1.y),
+            ] {
+                if p == 0. {
+                    if q < 0. {
+                        return None;
+                    }
+                } else {
+                    let r = q / p;
+                    if p < 0. {
+                        t0 = t0.max(r);
+                    } else {
+                        t1 = t1.min(r);
+                    }
+                }
+            }
+            if t0 > t1 {
+                return None;
+            }
+            Some([
+                Point::new(p1.x + dx * t0, p1.y + dy * t0),
+                Point::new(p1.x + dx * t1, p1.y + dy * t1),
+            ])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pt(x: f64, y: f64) -> Point {
+        Point::new(x, y)
+    }
+
+    // signed, positive for counter-clockwise
+    fn area(tri: &Triangle) -> f64 {
+        let [a, b, c] = tri;
+        ((b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y)) / 2.
+    }
+
+    fn total_area(triangles: &[Triangle]) -> f64 {
+        triangles.iter().map(area).sum()
+    }
+
+    fn unit() -> Bounds {
+        Bounds::new(0., 1., 0., 1.)
+    }
+
+    #[test]
+    fn triangles_inside_are_kept_as_they_are() {
+        let tri = [pt(0., 0.), pt(1., 0.), pt(0., 1.)];
+        assert_eq!(clip_triangles(&[tri], &unit()), vec![tri]);
+    }
+
+    #[test]
+    fn triangles_outside_are_dropped() {
+        let beside = [pt(2., 0.), pt(3., 0.), pt(2., 1.)];
+        // every corner is outside, but on different sides
+        let around = [pt(-1., -1.), pt(-0.5, -1.), pt(-1., -0.5)];
+        assert!(clip_triangles(&[beside, around], &unit()).is_empty());
+    }
+
+    #[test]
+    fn straddling_triangles_are_cut_at_the_edges() {
+        // half of it is right of x = 1
+        let tri = [pt(0., 0.), pt(2., 0.), pt(0., 1.)];
+        let clipped = clip_triangles(&[tri], &unit());
+        assert!((total_area(&clipped) - 0.75).abs() < 1e-12);
+        assert!(clipped.iter().all(|tri| area(tri) > 0.));
+        assert!(clipped.iter().flatten().all(|&pt| unit().contains(pt)));
+
+        // clockwise triangles stay clockwise
+        let tri = [pt(0.5, -1.), pt(-1., 0.5), pt(2., 2.)];
+        let clipped = clip_triangles(&[tri], &unit());
+        // corners on an edge can leave slivers with no area
+        assert!(clipped.iter().all(|tri| area(tri) <= 0.));
+        assert!((total_area(&clipped) + 1.).abs() < 1e-12);
+        assert!(clipped.iter().flatten().all(|&pt| unit().contains(pt)));
+
+        // covering the whole box leaves just the box
+        let tri = [pt(-1., -1.), pt(5., -1.), pt(-1., 5.)];
+        let clipped = clip_triangles(&[tri], &unit());
+        assert!((total_area(&clipped) - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn only_the_x_range_is_clipped_without_y_bounds() {
+        let tri = [pt(0.5, -10.), pt(2., 10.), pt(0.5, 10.)];
+        let clipped = clip_triangles(&[tri], &Bounds::x_range(0., 1.));
+        assert!(clipped.iter().flatten().all(|pt| pt.x <= 1.));
+        assert!(clipped.iter().flatten().any(|pt| pt.y == -10.));
+    }
+
+    #[test]
+    fn lines_inside_are_kept_as_they_are() {
+        let line = [pt(0.1, 0.2), pt(0.9, 1.)];
+        assert_eq!(clip_lines(&[line], &unit()), vec![line]);
+    }
+
+    #[test]
+    fn lines_outside_are_dropped() {
+        let lines = [
+            [pt(2., 0.), pt(3., 1.)],
+            // parallel to an edge, outside it
+            [pt(-1., 0.), pt(-1., 1.)],
+            // passes by a corner
+            [pt(2., 0.), pt(0., 2.2)],
+        ];
+        assert!(clip_lines(&lines, &unit()).is_empty());
+    }
+
+    #[test]
+    fn straddling_lines_are_cut_at_the_edges() {
+        let lines = [[pt(-1., 0.5), pt(3., 0.5)], [pt(0.5, 0.5), pt(0.5, 2.)]];
+        assert_eq!(
+            clip_lines(&lines, &unit()),
+            vec![[pt(0., 0.5), pt(1., 0.5)], [pt(0.5, 0.5), pt(0.5, 1.)]]
+        );
+        let diagonal = clip_lines(&[[pt(-1., -1.), pt(2., 2.)]], &unit());
+        assert_eq!(diagonal, vec![[pt(0., 0.), pt(1., 1.)]]);
+    }
+
+    #[test]
+    fn columns_outside_the_x_range_are_dropped() {
+        let columns: Vec<Line> = (0..10)
+            .map(|x| [pt(x as f64, 0.), pt(x as f64, 1.)])
+            .collect();
+        let bounds = Bounds::x_range(3.5, 5.5);
+        // the ones next to the range are kept
+        assert_eq!(clip_columns(&columns, &bounds), columns[3..7].to_vec());
+        assert_eq!(
+            clip_columns(&columns, &Bounds::x_range(-5., 0.5)),
+            columns[..2].to_vec()
+        );
+        assert_eq!(
+            clip_columns(&columns, &Bounds::x_range(8.5, 20.)),
+            columns[8..].to_vec()
+        );
+        assert_eq!(clip_columns(&columns, &Bounds::x_range(-5., 20.)), columns);
+        assert!(clip_columns(&columns, &Bounds::x_range(20., 30.)).is_empty());
+        assert!(clip_columns(&columns, &Bounds::x_range(-30., -20.)).is_empty());
+
+        // a column with one end in the range counts as in it
+        let slanted = [
+            [pt(0., 0.), pt(2., 1.)],
+            [pt(3., 0.), pt(5., 1.)],
+            [pt(6., 0.), pt(7., 1.)],
+        ];
+        assert_eq!(
+            clip_columns(&slanted, &Bounds::x_range(1.5, 2.5)),
+            slanted[..2].to_vec()
+        );
+    }
+}
@@ -0,0 +1,55 @@
+pub type Rgb = [u8; 3];
+
+// port of d3.interpolateRainbow, a cubehelix rainbow that wraps around at t = 0 and t = 1
+// https://github.com/d3/d3-scale-chromatic/blob/main/src/sequential-multi/rainbow.js
+pub fn interpolate_rainbow(t: f64) -> Rgb {
+    let t = if !(0. ..=1.).contains(&t) {
+        t - t.floor()
+    } else {
+        t
+    };
+    let ts = (t - 0.5).abs();
+    cubehelix_to_rgb(360. * t - 100., 1.5 - 1.5 * ts, 0.8 - 0.9 * ts)
+}
+
+// https://github.com/d3/d3-color/blob/main/src/cubehelix.js
+fn cubehelix_to_rgb(hue: f64, saturation: f64, lightness: f64) -> Rgb {
+    const A: f64 = -0.14861;
+    const B: f64 = 1.78277;
+    const C: f64 = -0.29227;
+    const D: f64 = -0.90649;
+    const E: f64 = 1.97294;
+
+    let h = (hue + 120.).to_radians();
+    let a = saturation * lightness * (1. - lightness);
+    let (sin_h, cos_h) = h.sin_cos();
+    [
+        lightness + a * (A * cos_h + B * sin_h),
+        lightness + a * (C * cos_h + D * sin_h),
+        lightness + a * (E * cos_h),
+    ]
+    .map(|channel| (channel * 255.).round().clamp(0., 255.) as u8)
+}
+
+// unique color for every index that's never black, so an object can be found by reading
+//   back a single pixel of the picking canvas
+pub fn pick_color(idx: usize) -> Rgb {
+    let id = idx as u32 + 1;
+    assert!(
+        id <= 0xffffff,
+        "Too many objects to give each a unique color"
+    );
+    [(id >> 16) as u8, (id >> 8) as u8, id as u8]
+}
+
+pub fn to_hex(color: Rgb) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+// deterministic value in [-1, 1] for `key`, FNV-1a so it's stable across builds
+pub fn key_noise(key: &str) -> f64 {
+    let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    (hash >> 11) as f64 / (1u64 << 53) as f64 * 2. - 1.
+}
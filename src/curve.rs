@@ -0,0 +1,82 @@
+use crate::Point;
+use svgtypes::PathSegment::{self, *};
+
+// port of d3.curveBasis, produces the same segments d3.line().curve(d3.curveBasis) would
+// https://github.com/d3/d3-shape/blob/main/src/curve/basis.js
+pub fn curve_basis(points: &[Point]) -> Vec<PathSegment> {
+    let mut segments: Vec<PathSegment> = Vec::with_capacity(points.len() + 2);
+    let (first, rest) = match points.split_first() {
+        Some(split) => split,
+        None => return segments,
+    };
+    segments.push(MoveTo {
+        abs: true,
+        x: first.x,
+        y: first.y,
+    });
+
+    // p0 and p1 are the two points before the current one
+    let (mut p0, mut p1) = (*first, *first);
+    for (idx, &pt) in rest.iter().enumerate() {
+        match idx {
+            0 => (),
+            1 => {
+                segments.push(LineTo {
+                    abs: true,
+                    x: (5. * p0.x + p1.x) / 6.,
+                    y: (5. * p0.y + p1.y) / 6.,
+                });
+                segments.push(basis_segment(p0, p1, pt));
+            }
+            _ => segments.push(basis_segment(p0, p1, pt)),
+        }
+        p0 = p1;
+        p1 = pt;
+    }
+
+    if points.len() > 2 {
+        segments.push(basis_segment(p0, p1, p1));
+    }
+    if points.len() > 1 {
+        segments.push(LineTo {
+            abs: true,
+            x: p1.x,
+            y: p1.y,
+        });
+    }
+    segments
+}
+
+// curve_basis(points)[first_segment..] without building the segments before it
+// segment k >= 2 only depends on points k - 2 to k, so when a point is appended to a curve
+//   of 3 or more points every segment from points.len() - 1 on is new and the rest stay
+pub fn curve_basis_from(points: &[Point], first_segment: usize) -> Vec<PathSegment> {
+    let n = points.len();
+    if first_segment < 2 || n < 3 || first_segment > n {
+        let mut segments = curve_basis(points);
+        return segments.split_off(first_segment.min(segments.len()));
+    }
+    let mut segments: Vec<PathSegment> = (first_segment..n)
+        .map(|k| basis_segment(points[k - 2], points[k - 1], points[k]))
+        .collect();
+    let (p0, p1) = (points[n - 2], points[n - 1]);
+    segments.push(basis_segment(p0, p1, p1));
+    segments.push(LineTo {
+        abs: true,
+        x: p1.x,
+        y: p1.y,
+    });
+    segments
+}
+
+fn basis_segment(p0: Point, p1: Point, pt: Point) -> PathSegment {
+    CurveTo {
+        abs: true,
+        x1: (2. * p0.x + p1.x) / 3.,
+        y1: (2. * p0.y + p1.y) / 3.,
+        x2: (p0.x + 2. * p1.x) / 3.,
+        y2: (p0.y + 2. * p1.y) / 3.,
+        x: (p0.x + 4. * p1.x + pt.x) / 6.,
+        y: (p0.y + 4. * p1.y + pt.y) / 6.,
+    }
+}
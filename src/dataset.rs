@@ -0,0 +1,282 @@
+use crate::binning::{bin_points, BinnedSeries, DataPoint};
+use crate::filter::FilterReport;
+use crate::normalize::ArtistNormalizer;
+use crate::records::ListenRecord;
+use crate::spans::ListenSpans;
+use crate::streamgraph::{collect_points, Streamgraph, StreamgraphConfig};
+use std::collections::HashMap;
+
+// a chart that keeps taking records after it's built, e.g. from a second batch of files
+// only the bins the new records land in are recounted, and only series whose outline or color
+//   changed are triangulated again
+pub struct Dataset {
+    config: StreamgraphConfig,
+    state: State,
+    graph: Streamgraph,
+    // series that changed since the last mark_synced(), indexed like graph.keys
+    dirty: Vec<bool>,
+}
+
+// everything the chart is laid out from
+#[derive(Clone)]
+struct State {
+    // every record added so far, including the ones filtered out
+    records: Vec<ListenRecord>,
+    // sorted by timestamp
+    points: Vec<DataPoint>,
+    // before smoothing and folding, those are redone from this on every update
+    raw: BinnedSeries,
+    spans: ListenSpans,
+    // knows the spelling every artist was first seen under
+    artists: ArtistNormalizer,
+    filter_report: FilterReport,
+}
+
+impl State {
+    fn new(config: &StreamgraphConfig) -> Result<Self, String> {
+        Ok(Self {
+            records: Vec::new(),
+            points: Vec::new(),
+            raw: bin_points(&[], config.time_step, config.time_zone)?,
+            spans: ListenSpans::new(config.min_first_listen_ms),
+            artists: ArtistNormalizer::new(&config.artists),
+            filter_report: FilterReport::default(),
+        })
+    }
+}
+
+impl Dataset {
+    pub fn new(config: StreamgraphConfig) -> Result<Self, String> {
+        config.validate()?;
+        Ok(Self {
+            state: State::new(&config)?,
+            graph: Streamgraph::empty(config.clone()),
+            dirty: Vec::new(),
+            config,
+        })
+    }
+
+    pub fn graph(&self) -> &Streamgraph {
+        &self.graph
+    }
+
+    // returns how many series changed
+    pub fn add_records(&mut self, records: &[ListenRecord]) -> Result<usize, String> {
+        self.update(records, false)
+    }
+
+    // makes the chart show exactly `records`, e.g. the whole merged history after each upload
+    // records that are already charted are left alone and new ones are added as with
+    //   add_records(), but if any charted record is missing, say a regular history row an
+    //   extended one took the place of, the chart is built again from `records`
+    // returns how many series changed
+    pub fn replace_records(&mut self, records: &[ListenRecord]) -> Result<usize, String> {
+        let mut charted: HashMap<&ListenRecord, usize> = HashMap::new();
+        for record in &self.state.records {
+            *charted.entry(record).or_insert(0) += 1;
+        }
+        let mut added: Vec<ListenRecord> = Vec::new();
+        for record in records {
+            match charted.get_mut(record) {
+                Some(count) if *count > 0 => *count -= 1,
+                _ => added.push(record.clone()),
+            }
+        }
+        if charted.values().all(|&count| count == 0) {
+            self.update(&added, false)
+        } else {
+            self.update(records, true)
+        }
+    }
+
+    // the batch is worked out on a copy of the state that's only kept once the new chart is
+    //   built, so after an error the dataset is as it was and the batch can be added again
+    // `from_scratch` starts the copy empty, areas that come out the same are still reused
+    fn update(&mut self, records: &[ListenRecord], from_scratch: bool) -> Result<usize, String> {
+        let mut state = if from_scratch {
+            State::new(&self.config)?
+        } else {
+            self.state.clone()
+        };
+        let (mut new_points, batch_report) =
+            collect_points(records, &self.config, &mut state.spans, &mut state.artists)?;
+        state.records.extend_from_slice(records);
+        state.filter_report.merge(&batch_report);
+        if new_points.is_empty() && !from_scratch {
+            self.graph.filter_report = state.filter_report.clone();
+            self.state = state;
+            return Ok(0);
+        }
+
+        new_points.sort_by_key(|p| p.timestamp);
+        let mut points = Vec::with_capacity(state.points.len() + new_points.len());
+        points.extend_from_slice(&state.points);
+        points.extend_from_slice(&new_points);
+        // both halves are sorted already, which the stable sort picks up on
+        points.sort_by_key(|p| p.timestamp);
+        state.raw.add_points(&new_points, &points)?;
+        state.points = points;
+
+        let (graph, changed) = Streamgraph::layout(
+            self.config.clone(),
+            &state.raw,
+            &state.points,
+            &state.spans,
+            state.filter_report.clone(),
+            Some(&self.graph),
+        )?;
+        self.state = state;
+        self.graph = graph;
+
+        self.dirty.resize(changed.len(), true);
+        for (dirty, &changed) in self.dirty.iter_mut().zip(&changed) {
+            *dirty |= changed;
+        }
+        Ok(changed.iter().filter(|&&changed| changed).count())
+    }
+
+    pub fn is_dirty(&self, series_idx: usize) -> bool {
+        self.dirty.get(series_idx).copied().unwrap_or(true)
+    }
+
+    // call once every context showing this dataset has been updated
+    pub fn mark_synced(&mut self) {
+        self.dirty.fill(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binning::TimeStep;
+    use crate::merge::{merge_histories, DEFAULT_MERGE_TOLERANCE_MS};
+    use crate::stack::{StackOffset, StackOrder};
+
+    const MINUTE: i64 = 60 * 1000;
+    const DAY: i64 = 24 * 60 * MINUTE;
+
+    fn play(artist: &str, day: i64, platform: Option<&str>) -> ListenRecord {
+        ListenRecord {
+            timestamp: day * DAY,
+            ms_played: 3 * MINUTE as u64,
+            track_name: format!("{} song", artist),
+            artist_name: artist.into(),
+            platform: platform.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    fn config() -> StreamgraphConfig {
+        StreamgraphConfig {
+            time_step: TimeStep::Day,
+            ..Default::default()
+        }
+    }
+
+    fn totals(graph: &Streamgraph) -> Vec<(String, f64)> {
+        graph
+            .keys
+            .iter()
+            .map(|info| (info.key.clone(), info.total_score))
+            .collect()
+    }
+
+    fn merged(extended: &[ListenRecord], regular: &[ListenRecord]) -> Vec<ListenRecord> {
+        merge_histories(extended, regular, DEFAULT_MERGE_TOLERANCE_MS).records
+    }
+
+    #[test]
+    fn batches_add_up_to_a_full_build() {
+        let records: Vec<ListenRecord> = (0..6)
+            .map(|day| play(["a", "b", "c"][day as usize % 3], day, None))
+            .collect();
+        let mut dataset = Dataset::new(config()).unwrap();
+        dataset.add_records(&records[..2]).unwrap();
+        dataset.add_records(&records[2..]).unwrap();
+
+        let full = Streamgraph::build(&records, config()).unwrap();
+        assert_eq!(totals(dataset.graph()), totals(&full));
+        assert_eq!(dataset.graph().xs, full.xs);
+    }
+
+    #[test]
+    fn replacing_with_more_records_only_adds_them() {
+        let records: Vec<ListenRecord> = (0..4).map(|day| play("a", day, None)).collect();
+        let mut dataset = Dataset::new(config()).unwrap();
+        dataset.replace_records(&records[..2]).unwrap();
+        dataset.mark_synced();
+        dataset.replace_records(&records).unwrap();
+        assert_eq!(dataset.state.records, records);
+        assert_eq!(
+            totals(dataset.graph()),
+            [("a".into(), 4. * 3. * MINUTE as f64)]
+        );
+
+        // nothing new, nothing changes
+        dataset.mark_synced();
+        assert_eq!(dataset.replace_records(&records).unwrap(), 0);
+        assert!(!dataset.is_dirty(0));
+    }
+
+    #[test]
+    fn extended_rows_uploaded_later_take_the_place_of_regular_ones() {
+        let regular = [play("a", 0, None), play("b", 1, None)];
+        let extended = [play("a", 0, Some("android")), play("c", 2, Some("android"))];
+
+        let mut dataset = Dataset::new(config()).unwrap();
+        dataset.replace_records(&merged(&[], &regular)).unwrap();
+        let records = merged(&extended, &regular);
+        dataset.replace_records(&records).unwrap();
+
+        let full = Streamgraph::build(&records, config()).unwrap();
+        assert_eq!(totals(dataset.graph()), totals(&full));
+        assert_eq!(dataset.graph().keys[0].total_score, 3. * MINUTE as f64);
+        assert_eq!(dataset.state.records, records);
+    }
+
+    #[test]
+    fn regular_rows_uploaded_later_are_not_counted_again() {
+        let regular = [play("a", 0, None), play("b", 1, None)];
+        let extended = [play("a", 0, Some("android"))];
+
+        let mut dataset = Dataset::new(config()).unwrap();
+        dataset.replace_records(&merged(&extended, &[])).unwrap();
+        let records = merged(&extended, &regular);
+        dataset.replace_records(&records).unwrap();
+
+        let full = Streamgraph::build(&records, config()).unwrap();
+        assert_eq!(totals(dataset.graph()), totals(&full));
+        assert_eq!(dataset.graph().keys[0].total_score, 3. * MINUTE as f64);
+    }
+
+    #[test]
+    fn series_that_moved_are_redrawn() {
+        let at = |artist: &str, hour: i64| ListenRecord {
+            timestamp: hour * 60 * MINUTE,
+            ..play(artist, 0, None)
+        };
+        let config = StreamgraphConfig {
+            order: StackOrder::Explicit(vec!["c".into(), "b".into(), "a".into()]),
+            offset: StackOffset::None,
+            color_noise: 0.,
+            ..config()
+        };
+        let mut dataset = Dataset::new(config).unwrap();
+        dataset
+            .replace_records(&[at("c", 0), at("a", 1), at("b", 2), at("c", 30)])
+            .unwrap();
+        let b = dataset.graph().keys[2].clone();
+        dataset.mark_synced();
+
+        // a is heard after b now, b has the same outline and color but comes second
+        dataset
+            .replace_records(&[at("c", 0), at("a", 3), at("b", 2), at("c", 30)])
+            .unwrap();
+        let keys = &dataset.graph().keys;
+        assert_eq!(keys[1].key, "b");
+        assert_eq!(keys[1].color, b.color);
+        assert!(dataset.is_dirty(1));
+        assert!(dataset.is_dirty(2));
+        assert!(!dataset.is_dirty(0));
+    }
+}
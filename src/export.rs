@@ -0,0 +1,368 @@
+use crate::filter::FilterReport;
+use crate::streamgraph::{KeyInfo, Streamgraph, StreamgraphConfig};
+use crate::zone::Zone;
+use chrono::{FixedOffset, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExportFormat {
+    // one row per bin, one column per key
+    WideCsv,
+    // one row per bin and key
+    LongCsv,
+    Json,
+}
+
+// what goes in the key columns of a wide csv
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExportValue {
+    // binned score after smoothing, what the stack is made from
+    Score,
+    // bottom and top of the key's layer
+    Y0,
+    Y1,
+}
+
+// csvs start with "# " lines holding the config (filters included) and the filter report as
+//   json, e.g. pandas.read_csv(path, comment="#")
+// csv timestamps are RFC 3339 in the chart's time zone, json ones are ms since epoch
+pub fn export(
+    graph: &Streamgraph,
+    format: ExportFormat,
+    value: ExportValue,
+) -> Result<String, String> {
+    match format {
+        ExportFormat::WideCsv => export_wide_csv(graph, value),
+        ExportFormat::LongCsv => export_long_csv(graph),
+        ExportFormat::Json => export_json(graph),
+    }
+}
+
+pub fn export_wide_csv(graph: &Streamgraph, value: ExportValue) -> Result<String, String> {
+    let mut csv = csv_preamble(graph)?;
+    let header: Vec<&str> = ["bin_start", "bin_end"]
+        .into_iter()
+        .chain(graph.binned.keys.iter().map(String::as_str))
+        .collect();
+    csv_row(&mut csv, header);
+
+    let zone = graph.binned.zone;
+    for bin in 0..graph.binned.n_bins() {
+        let mut row = vec![
+            format_timestamp(graph.binned.bin_start(bin), zone)?,
+            format_timestamp(graph.binned.bin_end(bin), zone)?,
+        ];
+        for (scores, layer) in graph.binned.scores.iter().zip(&graph.layers) {
+            let val = match value {
+                ExportValue::Score => scores[bin],
+                ExportValue::Y0 => layer.y0[bin],
+                ExportValue::Y1 => layer.y1[bin],
+            };
+            row.push(val.to_string());
+        }
+        csv_row(&mut csv, row);
+    }
+    Ok(csv)
+}
+
+pub fn export_long_csv(graph: &Streamgraph) -> Result<String, String> {
+    let mut csv = csv_preamble(graph)?;
+    csv_row(
+        &mut csv,
+        ["bin_start", "bin_end", "key", "score", "y0", "y1"],
+    );
+
+    let zone = graph.binned.zone;
+    for bin in 0..graph.binned.n_bins() {
+        let bin_start = format_timestamp(graph.binned.bin_start(bin), zone)?;
+        let bin_end = format_timestamp(graph.binned.bin_end(bin), zone)?;
+        for ((key, scores), layer) in graph
+            .binned
+            .keys
+            .iter()
+            .zip(&graph.binned.scores)
+            .zip(&graph.layers)
+        {
+            csv_row(
+                &mut csv,
+                [
+                    bin_start.clone(),
+                    bin_end.clone(),
+                    key.clone(),
+                    scores[bin].to_string(),
+                    layer.y0[bin].to_string(),
+                    layer.y1[bin].to_string(),
+                ],
+            );
+        }
+    }
+    Ok(csv)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonExport<'a> {
+    config: &'a StreamgraphConfig,
+    filter_report: &'a FilterReport,
+    bin_starts: &'a [i64],
+    bin_ends: &'a [i64],
+    // keys summed into the "Other" series
+    folded_keys: &'a [String],
+    series: Vec<JsonSeries<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonSeries<'a> {
+    #[serde(flatten)]
+    info: &'a KeyInfo,
+    scores: &'a [f64],
+    y0: &'a [f64],
+    y1: &'a [f64],
+}
+
+pub fn export_json(graph: &Streamgraph) -> Result<String, String> {
+    let edges = &graph.binned.bin_edges;
+    let n_bins = graph.binned.n_bins();
+    let export = JsonExport {
+        config: &graph.config,
+        filter_report: &graph.filter_report,
+        bin_starts: &edges[..n_bins],
+        bin_ends: edges.get(1..).unwrap_or_default(),
+        folded_keys: graph.folded_keys(),
+        series: graph
+            .keys
+            .iter()
+            .zip(&graph.binned.scores)
+            .zip(&graph.layers)
+            .map(|((info, scores), layer)| JsonSeries {
+                info,
+                scores,
+                y0: &layer.y0,
+                y1: &layer.y1,
+            })
+            .collect(),
+    };
+    serde_json::to_string_pretty(&export).map_err(|e| e.to_string())
+}
+
+fn csv_preamble(graph: &Streamgraph) -> Result<String, String> {
+    let config = serde_json::to_string(&graph.config).map_err(|e| e.to_string())?;
+    let report = serde_json::to_string(&graph.filter_report).map_err(|e| e.to_string())?;
+    Ok(format!(
+        "# config: {}\n# filter report: {}\n",
+        config, report
+    ))
+}
+
+fn csv_row<S: AsRef<str>>(csv: &mut String, fields: impl IntoIterator<Item = S>) {
+    for (idx, field) in fields.into_iter().enumerate() {
+        if idx > 0 {
+            csv.push(',');
+        }
+        let field = field.as_ref();
+        if field.contains([',', '"', '\n', '\r']) || field.starts_with('#') {
+            let _ = write!(csv, "\"{}\"", field.replace('"', "\"\""));
+        } else {
+            csv.push_str(field);
+        }
+    }
+    csv.push('\n');
+}
+
+fn format_timestamp(timestamp: i64, zone: Zone) -> Result<String, String> {
+    let local = zone.to_local(timestamp)?;
+    let offset_secs = (local.and_utc().timestamp_millis() - timestamp) / 1000;
+    let offset = FixedOffset::east_opt(offset_secs as i32).ok_or("Invalid UTC offset")?;
+    let dt = offset
+        .timestamp_millis_opt(timestamp)
+        .single()
+        .ok_or_else(|| format!("Timestamp {} out of range", timestamp))?;
+    Ok(dt.to_rfc3339())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binning::TimeStep;
+    use crate::records::ListenRecord;
+    use crate::stack::StackOffset;
+    use chrono::NaiveDate;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32) -> i64 {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp_millis()
+    }
+
+    fn play(artist: &str, timestamp: i64) -> ListenRecord {
+        ListenRecord {
+            timestamp,
+            ms_played: 60_000,
+            track_name: "t".into(),
+            artist_name: artist.into(),
+            ..Default::default()
+        }
+    }
+
+    // awkward names, two days
+    fn graph() -> Streamgraph {
+        let records = [
+            play("Crosby, Stills & Nash", utc(2021, 3, 1, 12)),
+            play("The \"Band\"", utc(2021, 3, 1, 13)),
+            play("#1 Dads", utc(2021, 3, 2, 12)),
+        ];
+        let config = StreamgraphConfig {
+            time_step: TimeStep::Day,
+            offset: StackOffset::None,
+            ..Default::default()
+        };
+        Streamgraph::build(&records, config).unwrap()
+    }
+
+    fn csv_lines(csv: &str) -> Vec<&str> {
+        csv.lines().filter(|line| !line.starts_with("# ")).collect()
+    }
+
+    #[test]
+    fn fields_are_quoted_when_they_need_to_be() {
+        let mut csv = String::new();
+        csv_row(
+            &mut csv,
+            [
+                "plain",
+                "a,b",
+                "say \"hi\"",
+                "two\nlines",
+                "#hash",
+                "mid#dle",
+                "",
+            ],
+        );
+        assert_eq!(
+            csv,
+            "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\"#hash\",mid#dle,\n"
+        );
+    }
+
+    #[test]
+    fn csvs_start_with_the_config_and_filter_report() {
+        let csv = export_wide_csv(&graph(), ExportValue::Score).unwrap();
+        let preamble: Vec<&str> = csv.lines().take(2).collect();
+        let config = preamble[0].strip_prefix("# config: ").unwrap();
+        let config: serde_json::Value = serde_json::from_str(config).unwrap();
+        assert_eq!(config["timeStep"], "day");
+        let report = preamble[1].strip_prefix("# filter report: ").unwrap();
+        let report: serde_json::Value = serde_json::from_str(report).unwrap();
+        assert_eq!(report["kept"], 3);
+    }
+
+    #[test]
+    fn wide_csv_reads_back() {
+        let graph = graph();
+        let csv = export_wide_csv(&graph, ExportValue::Score).unwrap();
+        let mut reader = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .from_reader(csv.as_bytes());
+        let header: Vec<String> = reader.headers().unwrap().iter().map(String::from).collect();
+        assert_eq!(
+            header,
+            [
+                "bin_start",
+                "bin_end",
+                "Crosby, Stills & Nash",
+                "The \"Band\"",
+                "#1 Dads"
+            ]
+        );
+        let rows: Vec<Vec<String>> = reader
+            .records()
+            .map(|row| row.unwrap().iter().map(String::from).collect())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                [
+                    "2021-03-01T00:00:00+00:00",
+                    "2021-03-02T00:00:00+00:00",
+                    "60000",
+                    "60000",
+                    "0"
+                ],
+                [
+                    "2021-03-02T00:00:00+00:00",
+                    "2021-03-03T00:00:00+00:00",
+                    "0",
+                    "0",
+                    "60000"
+                ],
+            ]
+        );
+
+        // the top layer reaches the stack's total
+        let csv = export_wide_csv(&graph, ExportValue::Y1).unwrap();
+        let tops: Vec<f64> = csv_lines(&csv)[1]
+            .split(',')
+            .skip(2)
+            .map(|y| y.parse().unwrap())
+            .collect();
+        assert_eq!(tops.iter().cloned().fold(0., f64::max), 120_000.);
+    }
+
+    #[test]
+    fn long_csv_has_a_row_per_bin_and_key() {
+        let csv = export_long_csv(&graph()).unwrap();
+        let lines = csv_lines(&csv);
+        assert_eq!(lines[0], "bin_start,bin_end,key,score,y0,y1");
+        assert_eq!(lines.len(), 1 + 2 * 3);
+        assert_eq!(
+            lines[2],
+            "2021-03-01T00:00:00+00:00,2021-03-02T00:00:00+00:00,\"The \"\"Band\"\"\",60000,60000,120000"
+        );
+        assert!(lines[6].contains(",\"#1 Dads\",60000,0,60000"));
+    }
+
+    #[test]
+    fn json_has_every_series() {
+        let json: serde_json::Value =
+            serde_json::from_str(&export_json(&graph()).unwrap()).unwrap();
+        assert_eq!(json["binStarts"].as_array().unwrap().len(), 2);
+        assert_eq!(json["binEnds"][1], utc(2021, 3, 3, 0));
+        assert_eq!(json["series"][2]["key"], "#1 Dads");
+        assert_eq!(json["series"][2]["scores"], serde_json::json!([0., 60000.]));
+        assert_eq!(json["filterReport"]["kept"], 3);
+    }
+
+    #[test]
+    fn timestamps_carry_the_offset_in_use_at_the_time() {
+        let berlin = Zone::parse("Europe/Berlin").unwrap();
+        // clocks go forward at 01:00 utc on the 28th and back at 01:00 utc on the 31st
+        let cases = [
+            (utc(2021, 3, 28, 0), "2021-03-28T01:00:00+01:00"),
+            (utc(2021, 3, 28, 1), "2021-03-28T03:00:00+02:00"),
+            (utc(2021, 10, 31, 0), "2021-10-31T02:00:00+02:00"),
+            (utc(2021, 10, 31, 1), "2021-10-31T02:00:00+01:00"),
+        ];
+        for (timestamp, formatted) in cases {
+            assert_eq!(format_timestamp(timestamp, berlin).unwrap(), formatted);
+        }
+        assert_eq!(
+            format_timestamp(utc(2021, 3, 28, 1), Zone::Utc).unwrap(),
+            "2021-03-28T01:00:00+00:00"
+        );
+        let fixed = Zone::parse("-05:30").unwrap();
+        assert_eq!(
+            format_timestamp(utc(2021, 3, 28, 1), fixed).unwrap(),
+            "2021-03-27T19:30:00-05:30"
+        );
+    }
+}
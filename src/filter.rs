@@ -0,0 +1,343 @@
+use crate::records::{ContentKind, GroupBy, ListenRecord};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+// which records make it into the chart, everything defaults to keeping the record
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RecordFilter {
+    pub min_ms_played: u64,
+    pub drop_skips: bool,
+    pub drop_incognito: bool,
+    pub drop_music: bool,
+    pub drop_podcasts: bool,
+    // ms since epoch, start is inclusive and end is exclusive
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    // keys as made by the grouping, None keeps every key
+    pub include_keys: Option<Vec<String>>,
+    pub exclude_keys: Vec<String>,
+}
+
+// records are counted under the first rule they fail, in this order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FilterReason {
+    // ms_played of 0, always dropped
+    ZeroPlay,
+    ShortPlay,
+    Skipped,
+    Incognito,
+    Music,
+    Podcast,
+    OutOfRange,
+    NotIncluded,
+    Excluded,
+    // nothing to group by, e.g. a podcast when grouping by artist, only the chart drops these
+    NoKey,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FilterReport {
+    pub kept: usize,
+    pub removed: BTreeMap<FilterReason, usize>,
+    // ms played of the removed records
+    pub removed_ms: BTreeMap<FilterReason, u64>,
+}
+
+impl FilterReport {
+    pub fn total_removed(&self) -> usize {
+        self.removed.values().sum()
+    }
+
+    // counts a record that was kept so far as removed
+    pub fn reject(&mut self, record: &ListenRecord, reason: FilterReason) {
+        self.kept -= 1;
+        self.count_removed(record, reason);
+    }
+
+    fn count_removed(&mut self, record: &ListenRecord, reason: FilterReason) {
+        *self.removed.entry(reason).or_insert(0) += 1;
+        *self.removed_ms.entry(reason).or_insert(0) += record.ms_played;
+    }
+
+    pub fn merge(&mut self, other: &FilterReport) {
+        self.kept += other.kept;
+        for (&reason, &count) in &other.removed {
+            *self.removed.entry(reason).or_insert(0) += count;
+        }
+        for (&reason, &ms) in &other.removed_ms {
+            *self.removed_ms.entry(reason).or_insert(0) += ms;
+        }
+    }
+}
+
+impl RecordFilter {
+    pub fn apply<'a>(
+        &self,
+        records: &'a [ListenRecord],
+        group_by: GroupBy,
+    ) -> (Vec<&'a ListenRecord>, FilterReport) {
+        let include: Option<HashSet<&str>> = self
+            .include_keys
+            .as_ref()
+            .map(|keys| keys.iter().map(String::as_str).collect());
+        let exclude: HashSet<&str> = self.exclude_keys.iter().map(String::as_str).collect();
+
+        let mut report = FilterReport::default();
+        let kept = records
+            .iter()
+            .filter(
+                |record| match self.check(record, group_by, &include, &exclude) {
+                    Ok(()) => {
+                        report.kept += 1;
+                        true
+                    }
+                    Err(reason) => {
+                        report.count_removed(record, reason);
+                        false
+                    }
+                },
+            )
+            .collect();
+        (kept, report)
+    }
+
+    fn check(
+        &self,
+        record: &ListenRecord,
+        group_by: GroupBy,
+        include: &Option<HashSet<&str>>,
+        exclude: &HashSet<&str>,
+    ) -> Result<(), FilterReason> {
+        if record.ms_played == 0 {
+            return Err(FilterReason::ZeroPlay);
+        }
+        if record.ms_played < self.min_ms_played {
+            return Err(FilterReason::ShortPlay);
+        }
+        if self.drop_skips && record.is_skip() {
+            return Err(FilterReason::Skipped);
+        }
+        if self.drop_incognito && record.incognito_mode == Some(true) {
+            return Err(FilterReason::Incognito);
+        }
+        match record.kind {
+            ContentKind::Music if self.drop_music => return Err(FilterReason::Music),
+            ContentKind::Podcast if self.drop_podcasts => return Err(FilterReason::Podcast),
+            _ => {}
+        }
+        let before_start = self.start.is_some_and(|start| record.timestamp < start);
+        let after_end = self.end.is_some_and(|end| record.timestamp >= end);
+        if before_start || after_end {
+            return Err(FilterReason::OutOfRange);
+        }
+        if include.is_some() || !exclude.is_empty() {
+            let key = group_by.key(record);
+            let key = key.as_deref();
+            if let Some(include) = include {
+                if !key.is_some_and(|key| include.contains(key)) {
+                    return Err(FilterReason::NotIncluded);
+                }
+            }
+            if key.is_some_and(|key| exclude.contains(key)) {
+                return Err(FilterReason::Excluded);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalize::ArtistNormalizer;
+    use crate::spans::ListenSpans;
+    use crate::streamgraph::{collect_points, StreamgraphConfig};
+
+    const MINUTE: u64 = 60 * 1000;
+
+    fn play(artist: &str, timestamp: i64, ms_played: u64) -> ListenRecord {
+        ListenRecord {
+            timestamp,
+            ms_played,
+            track_name: "t".into(),
+            artist_name: artist.into(),
+            ..Default::default()
+        }
+    }
+
+    fn strict() -> RecordFilter {
+        RecordFilter {
+            min_ms_played: MINUTE,
+            drop_skips: true,
+            drop_incognito: true,
+            drop_music: false,
+            drop_podcasts: true,
+            start: Some(100),
+            end: Some(200),
+            include_keys: Some(vec!["a".into(), "b".into()]),
+            exclude_keys: vec!["b".into()],
+        }
+    }
+
+    fn removed(report: &FilterReport) -> Vec<(FilterReason, usize)> {
+        report
+            .removed
+            .iter()
+            .map(|(&reason, &n)| (reason, n))
+            .collect()
+    }
+
+    fn reason(filter: &RecordFilter, record: &ListenRecord) -> Option<FilterReason> {
+        let (_, report) = filter.apply(std::slice::from_ref(record), GroupBy::Artist);
+        report.removed.keys().next().copied()
+    }
+
+    #[test]
+    fn records_count_under_the_first_rule_they_fail() {
+        // fails every rule, each step fixes the one it was counted under
+        let filter = RecordFilter {
+            drop_music: true,
+            ..strict()
+        };
+        let mut record = ListenRecord {
+            skipped: Some(true),
+            incognito_mode: Some(true),
+            ..play("c", 50, 0)
+        };
+        assert_eq!(reason(&filter, &record), Some(FilterReason::ZeroPlay));
+        record.ms_played = 1000;
+        assert_eq!(reason(&filter, &record), Some(FilterReason::ShortPlay));
+        record.ms_played = 2 * MINUTE;
+        assert_eq!(reason(&filter, &record), Some(FilterReason::Skipped));
+        record.skipped = Some(false);
+        assert_eq!(reason(&filter, &record), Some(FilterReason::Incognito));
+        record.incognito_mode = None;
+        assert_eq!(reason(&filter, &record), Some(FilterReason::Music));
+        record.kind = ContentKind::Podcast;
+        assert_eq!(reason(&filter, &record), Some(FilterReason::Podcast));
+        record.kind = ContentKind::Music;
+        assert_eq!(reason(&strict(), &record), Some(FilterReason::OutOfRange));
+        record.timestamp = 150;
+        assert_eq!(reason(&strict(), &record), Some(FilterReason::NotIncluded));
+        record.artist_name = "b".into();
+        assert_eq!(reason(&strict(), &record), Some(FilterReason::Excluded));
+        record.artist_name = "a".into();
+        assert_eq!(reason(&strict(), &record), None);
+    }
+
+    #[test]
+    fn report_counts_and_ms() {
+        let records = [
+            play("a", 150, 0),
+            play("a", 150, 1000),
+            play("a", 150, 2000),
+            ListenRecord {
+                reason_end: Some("fwdbtn".into()),
+                ..play("a", 150, 2 * MINUTE)
+            },
+            ListenRecord {
+                incognito_mode: Some(true),
+                ..play("a", 150, 2 * MINUTE)
+            },
+            ListenRecord {
+                kind: ContentKind::Podcast,
+                ..play("show", 150, 2 * MINUTE)
+            },
+            // the range is [start, end)
+            play("a", 99, 2 * MINUTE),
+            play("a", 100, 2 * MINUTE),
+            play("a", 199, 2 * MINUTE),
+            play("a", 200, 2 * MINUTE),
+            play("c", 150, 2 * MINUTE),
+            play("b", 150, 2 * MINUTE),
+        ];
+        let (kept, report) = strict().apply(&records, GroupBy::Artist);
+        assert_eq!(
+            kept.iter().map(|r| r.timestamp).collect::<Vec<_>>(),
+            [100, 199]
+        );
+        assert_eq!(report.kept, 2);
+        assert_eq!(
+            removed(&report),
+            [
+                (FilterReason::ZeroPlay, 1),
+                (FilterReason::ShortPlay, 2),
+                (FilterReason::Skipped, 1),
+                (FilterReason::Incognito, 1),
+                (FilterReason::Podcast, 1),
+                (FilterReason::OutOfRange, 2),
+                (FilterReason::NotIncluded, 1),
+                (FilterReason::Excluded, 1),
+            ]
+        );
+        assert_eq!(report.total_removed() + report.kept, records.len());
+        assert_eq!(report.removed_ms[&FilterReason::ShortPlay], 3000);
+        assert_eq!(report.removed_ms[&FilterReason::OutOfRange], 4 * MINUTE);
+
+        let mut merged = report.clone();
+        merged.merge(&report);
+        assert_eq!(merged.kept, 4);
+        assert_eq!(merged.removed[&FilterReason::ShortPlay], 4);
+        assert_eq!(merged.removed_ms[&FilterReason::ShortPlay], 6000);
+    }
+
+    #[test]
+    fn the_default_filter_only_drops_zero_plays() {
+        let records = [
+            play("a", 0, 0),
+            play("a", 0, 1),
+            ListenRecord {
+                skipped: Some(true),
+                incognito_mode: Some(true),
+                kind: ContentKind::Podcast,
+                ..play("a", -5, 1)
+            },
+        ];
+        let (kept, report) = RecordFilter::default().apply(&records, GroupBy::Artist);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(removed(&report), [(FilterReason::ZeroPlay, 1)]);
+    }
+
+    #[test]
+    fn records_without_a_key() {
+        let podcast = ListenRecord {
+            kind: ContentKind::Podcast,
+            ..play("show", 150, 2 * MINUTE)
+        };
+        // excluding keys keeps records without one, including keys doesn't
+        let exclude = RecordFilter {
+            exclude_keys: vec!["a".into()],
+            ..Default::default()
+        };
+        assert_eq!(reason(&exclude, &podcast), None);
+        let include = RecordFilter {
+            include_keys: Some(vec!["a".into()]),
+            ..Default::default()
+        };
+        assert_eq!(reason(&include, &podcast), Some(FilterReason::NotIncluded));
+
+        // the chart drops them after filtering
+        let config = StreamgraphConfig {
+            filter: exclude,
+            ..Default::default()
+        };
+        let records = [podcast, play("a", 150, MINUTE), play("b", 150, MINUTE)];
+        let (points, report) = collect_points(
+            &records,
+            &config,
+            &mut ListenSpans::new(MINUTE),
+            &mut ArtistNormalizer::new(&config.artists),
+        )
+        .unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(report.kept, 1);
+        assert_eq!(
+            removed(&report),
+            [(FilterReason::Excluded, 1), (FilterReason::NoKey, 1)]
+        );
+        assert_eq!(report.removed_ms[&FilterReason::NoKey], 2 * MINUTE);
+    }
+}
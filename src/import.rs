@@ -0,0 +1,457 @@
+use crate::parse::{
+    parse_rows, parse_timestamp, HistoryFormat, ParseReport, ParsedHistory, SkipReason,
+};
+use crate::records::{ContentKind, ListenRecord};
+use chrono::NaiveDateTime;
+use csv::{ReaderBuilder, StringRecord};
+use serde::Deserialize;
+
+// last.fm and youtube music don't say how long a play was, plays from them get this unless
+//   told otherwise, about the length of an average song
+pub const DEFAULT_ASSUMED_MS_PLAYED: u64 = 3 * 60 * 1000 + 30 * 1000;
+
+// column names are matched ignoring case, the first one present is used
+const LASTFM_ARTIST: &[&str] = &["artist", "artist_name", "artist name"];
+const LASTFM_ALBUM: &[&str] = &["album", "album_name", "album name"];
+const LASTFM_TRACK: &[&str] = &["track", "track_name", "track name", "title", "name"];
+const LASTFM_DATE: &[&str] = &["uts", "date", "utc_time", "time", "timestamp"];
+
+const APPLE_TRACK: &[&str] = &["song name", "content name"];
+const APPLE_ARTIST: &[&str] = &["artist name", "container artist name"];
+const APPLE_ALBUM: &[&str] = &["album name", "container album name"];
+const APPLE_MS_PLAYED: &[&str] = &["play duration milliseconds"];
+const APPLE_START: &[&str] = &["event start timestamp"];
+const APPLE_END: &[&str] = &["event end timestamp", "event received timestamp"];
+const APPLE_EVENT: &[&str] = &["event type"];
+const APPLE_END_REASON: &[&str] = &["end reason type"];
+const APPLE_SHUFFLE: &[&str] = &["shuffle play"];
+const APPLE_OFFLINE: &[&str] = &["offline"];
+
+// scrobbles from last.fm, either headerless artist,album,track,date rows (lastfm-to-csv) or
+//   a csv with a header naming those columns, dates can be unix seconds, rfc 3339 or
+//   "31 Jan 2021 12:34" in utc
+// scrobbles don't have a length, each one gets `assumed_ms_played`
+pub fn parse_lastfm_scrobbles(
+    bytes: &[u8],
+    assumed_ms_played: u64,
+) -> Result<ParsedHistory, String> {
+    let mut rows = CsvRows::new(bytes, HistoryFormat::LastFm);
+    let first = match rows.next_row() {
+        Some(first) => first,
+        None => return Ok(rows.finish()),
+    };
+    let columns = match Columns::from_header(&first) {
+        // not a header, so the default column order
+        cols if cols.find(LASTFM_ARTIST).is_none() => {
+            let columns = Columns::from_names(&["artist", "album", "track", "date"]);
+            rows.parse_row(&first, |row| lastfm_row(&columns, row, assumed_ms_played));
+            columns
+        }
+        cols => cols,
+    };
+    while let Some(row) = rows.next_row() {
+        rows.parse_row(&row, |row| lastfm_row(&columns, row, assumed_ms_played));
+    }
+    Ok(rows.finish())
+}
+
+fn lastfm_row(
+    columns: &Columns,
+    row: &StringRecord,
+    assumed_ms_played: u64,
+) -> Result<ListenRecord, SkipReason> {
+    let date = columns
+        .get(row, LASTFM_DATE)
+        .ok_or(SkipReason::MissingTimestamp)?;
+    Ok(ListenRecord {
+        timestamp: parse_scrobble_date(date).ok_or(SkipReason::InvalidTimestamp)?,
+        ms_played: assumed_ms_played,
+        track_name: columns
+            .get(row, LASTFM_TRACK)
+            .ok_or(SkipReason::MissingTrackName)?
+            .into(),
+        artist_name: columns
+            .get(row, LASTFM_ARTIST)
+            .ok_or(SkipReason::MissingArtistName)?
+            .into(),
+        album_name: columns.get(row, LASTFM_ALBUM).map(String::from),
+        kind: ContentKind::Music,
+        ..Default::default()
+    })
+}
+
+fn parse_scrobble_date(date: &str) -> Option<i64> {
+    if let Ok(secs) = date.parse::<i64>() {
+        return secs.checked_mul(1000);
+    }
+    if let Ok(timestamp) = parse_timestamp(date) {
+        return Some(timestamp);
+    }
+    ["%d %b %Y %H:%M", "%d %b %Y, %H:%M", "%d %b %Y %H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+        .map(|dt| dt.and_utc().timestamp_millis())
+}
+
+// true if the first line of `bytes` is the header of apple's play activity csv
+pub fn is_apple_music_csv(bytes: &[u8]) -> bool {
+    let first_line = bytes.split(|&b| b == b'\n').next().unwrap_or_default();
+    String::from_utf8_lossy(first_line)
+        .to_lowercase()
+        .contains(APPLE_MS_PLAYED[0])
+}
+
+// Apple Music Play Activity.csv, only PLAY_END events are plays, the rest are skipped
+// older exports have "Artist Name" and "Song Name", newer ones "Container Artist Name" and
+//   "Content Name"
+pub fn parse_apple_music_activity(bytes: &[u8]) -> Result<ParsedHistory, String> {
+    let mut rows = CsvRows::new(bytes, HistoryFormat::AppleMusic);
+    let columns = match rows.next_row() {
+        Some(header) => Columns::from_header(&header),
+        None => return Ok(rows.finish()),
+    };
+    if columns.find(APPLE_MS_PLAYED).is_none() {
+        return Err("Not an Apple Music play activity file".into());
+    }
+    while let Some(row) = rows.next_row() {
+        rows.parse_row(&row, |row| apple_music_row(&columns, row));
+    }
+    Ok(rows.finish())
+}
+
+fn apple_music_row(columns: &Columns, row: &StringRecord) -> Result<ListenRecord, SkipReason> {
+    if columns
+        .get(row, APPLE_EVENT)
+        .is_some_and(|event| event != "PLAY_END")
+    {
+        return Err(SkipReason::NotAPlay);
+    }
+    let ms_played: i64 = columns
+        .get(row, APPLE_MS_PLAYED)
+        .ok_or(SkipReason::MissingMsPlayed)?
+        .parse()
+        .map_err(|_| SkipReason::Malformed)?;
+    // some rows have negative durations
+    let ms_played = u64::try_from(ms_played).map_err(|_| SkipReason::Malformed)?;
+    let parse = |ts: &str| parse_timestamp(ts).map_err(|_| SkipReason::InvalidTimestamp);
+    let timestamp = match (columns.get(row, APPLE_START), columns.get(row, APPLE_END)) {
+        (Some(start), _) => parse(start)?,
+        (None, Some(end)) => parse(end)? - ms_played as i64,
+        (None, None) => return Err(SkipReason::MissingTimestamp),
+    };
+    let flag = |names: &[&str]| {
+        columns
+            .get(row, names)
+            .and_then(|val| val.to_lowercase().parse::<bool>().ok())
+    };
+    Ok(ListenRecord {
+        timestamp,
+        ms_played,
+        track_name: columns
+            .get(row, APPLE_TRACK)
+            .ok_or(SkipReason::MissingTrackName)?
+            .into(),
+        artist_name: columns
+            .get(row, APPLE_ARTIST)
+            .ok_or(SkipReason::MissingArtistName)?
+            .into(),
+        album_name: columns.get(row, APPLE_ALBUM).map(String::from),
+        kind: ContentKind::Music,
+        reason_end: columns.get(row, APPLE_END_REASON).map(String::from),
+        shuffle: flag(APPLE_SHUFFLE),
+        offline: flag(APPLE_OFFLINE),
+        skipped: columns
+            .get(row, APPLE_END_REASON)
+            .map(|reason| reason == "TRACK_SKIPPED_FORWARDS"),
+        ..Default::default()
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct YoutubeRow {
+    // "YouTube Music" or "YouTube"
+    header: Option<String>,
+    // "Watched <video title>"
+    title: Option<String>,
+    // the channel, "<artist> - Topic" for songs
+    subtitles: Option<Vec<YoutubeSubtitle>>,
+    time: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct YoutubeSubtitle {
+    name: Option<String>,
+}
+
+// watch-history.json from google takeout, only entries from youtube music are kept
+// the export doesn't have play lengths, each play gets `assumed_ms_played`
+// titles are "Watched ..." in english exports, other languages keep their prefix
+pub fn parse_youtube_music_history(
+    bytes: &[u8],
+    assumed_ms_played: u64,
+) -> Result<ParsedHistory, String> {
+    parse_rows(bytes, HistoryFormat::YoutubeMusic, |row: YoutubeRow| {
+        if row.header.as_deref() != Some("YouTube Music") {
+            return Err(SkipReason::NotAPlay);
+        }
+        let time = row.time.ok_or(SkipReason::MissingTimestamp)?;
+        let title = row.title.ok_or(SkipReason::MissingTrackName)?;
+        let channel = row
+            .subtitles
+            .and_then(|subtitles| subtitles.into_iter().next())
+            .and_then(|subtitle| subtitle.name)
+            .ok_or(SkipReason::MissingArtistName)?;
+        Ok(ListenRecord {
+            timestamp: parse_timestamp(&time).map_err(|_| SkipReason::InvalidTimestamp)?,
+            ms_played: assumed_ms_played,
+            track_name: title.strip_prefix("Watched ").unwrap_or(&title).into(),
+            artist_name: channel.strip_suffix(" - Topic").unwrap_or(&channel).into(),
+            kind: ContentKind::Music,
+            ..Default::default()
+        })
+    })
+}
+
+// rows of a csv file, counted into a report as they're parsed
+struct CsvRows<'a> {
+    reader: csv::Reader<&'a [u8]>,
+    records: Vec<ListenRecord>,
+    report: ParseReport,
+}
+
+impl<'a> CsvRows<'a> {
+    fn new(bytes: &'a [u8], format: HistoryFormat) -> Self {
+        let reader = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(bytes);
+        Self {
+            reader,
+            records: Vec::new(),
+            report: ParseReport::new(format),
+        }
+    }
+
+    // rows that aren't valid utf-8 are counted as malformed and skipped
+    fn next_row(&mut self) -> Option<StringRecord> {
+        let mut row = StringRecord::new();
+        loop {
+            match self.reader.read_record(&mut row) {
+                Ok(true) => return Some(row),
+                Ok(false) => return None,
+                Err(e) if e.is_io_error() => return None,
+                Err(_) => {
+                    self.report.rows += 1;
+                    self.report.skip(SkipReason::Malformed);
+                }
+            }
+        }
+    }
+
+    fn parse_row(
+        &mut self,
+        row: &StringRecord,
+        to_record: impl Fn(&StringRecord) -> Result<ListenRecord, SkipReason>,
+    ) {
+        self.report.rows += 1;
+        match to_record(row) {
+            Ok(record) => {
+                self.report.parsed += 1;
+                self.records.push(record);
+            }
+            Err(reason) => self.report.skip(reason),
+        }
+    }
+
+    fn finish(self) -> ParsedHistory {
+        ParsedHistory {
+            records: self.records,
+            report: self.report,
+        }
+    }
+}
+
+// lowercased column names of a csv
+struct Columns(Vec<String>);
+
+impl Columns {
+    fn from_header(header: &StringRecord) -> Self {
+        Self::from_names(&header.iter().collect::<Vec<_>>())
+    }
+
+    fn from_names(names: &[&str]) -> Self {
+        let names = names
+            .iter()
+            .map(|name| name.trim_start_matches('\u{feff}').trim().to_lowercase())
+            .collect();
+        Self(names)
+    }
+
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        names
+            .iter()
+            .find_map(|name| self.0.iter().position(|col| col == name))
+    }
+
+    // the first of `names` that has a non-empty value in `row`
+    fn get<'r>(&self, row: &'r StringRecord, names: &[&str]) -> Option<&'r str> {
+        names
+            .iter()
+            .filter_map(|name| self.0.iter().position(|col| col == name))
+            .filter_map(|idx| row.get(idx))
+            .map(str::trim)
+            .find(|val| !val.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2021-03-01T12:34:00Z
+    const T: i64 = 1_614_602_040_000;
+    const MINUTE: i64 = 60_000;
+
+    fn skipped(history: &ParsedHistory) -> Vec<(SkipReason, usize)> {
+        history
+            .report
+            .skipped
+            .iter()
+            .map(|(&reason, &n)| (reason, n))
+            .collect()
+    }
+
+    #[test]
+    fn lastfm_without_a_header() {
+        let csv = "Björk,Homogenic,Jóga,01 Mar 2021 12:34\n\
+                   \"Crosby, Stills & Nash\",,Helplessly Hoping,1614602100\n\
+                   Björk,Homogenic,Bachelorette,not a date\n";
+        let history = parse_lastfm_scrobbles(csv.as_bytes(), 1000).unwrap();
+        assert_eq!(history.report.format, HistoryFormat::LastFm);
+        assert_eq!(history.report.rows, 3);
+        assert_eq!(skipped(&history), [(SkipReason::InvalidTimestamp, 1)]);
+
+        // the first row is a play, not a header
+        let first = &history.records[0];
+        assert_eq!(first.timestamp, T);
+        assert_eq!(first.ms_played, 1000);
+        assert_eq!(first.artist_name, "Björk");
+        assert_eq!(first.album_name.as_deref(), Some("Homogenic"));
+        assert_eq!(first.track_name, "Jóga");
+
+        let second = &history.records[1];
+        assert_eq!(second.timestamp, T + MINUTE);
+        assert_eq!(second.artist_name, "Crosby, Stills & Nash");
+        assert_eq!(second.album_name, None);
+    }
+
+    #[test]
+    fn lastfm_with_a_header() {
+        // columns in any order, names in any case
+        let csv = "\u{feff}UTS,Track Name,Artist Name\n\
+                   1614602040,Track,Artist\n\
+                   2021-03-01T13:34:00+01:00,Track,\n";
+        let history = parse_lastfm_scrobbles(csv.as_bytes(), DEFAULT_ASSUMED_MS_PLAYED).unwrap();
+        assert_eq!(history.report.rows, 2);
+        assert_eq!(skipped(&history), [(SkipReason::MissingArtistName, 1)]);
+        assert_eq!(history.records.len(), 1);
+        assert_eq!(history.records[0].timestamp, T);
+        assert_eq!(history.records[0].ms_played, DEFAULT_ASSUMED_MS_PLAYED);
+        assert_eq!(history.records[0].track_name, "Track");
+        assert_eq!(history.records[0].artist_name, "Artist");
+
+        let empty = parse_lastfm_scrobbles(b"", 1000).unwrap();
+        assert_eq!(empty.report.rows, 0);
+    }
+
+    const APPLE_HEADER: &str = "Artist Name,Song Name,Album Name,Event Type,\
+                                Event Start Timestamp,Play Duration Milliseconds,\
+                                End Reason Type,Shuffle Play,Offline";
+
+    #[test]
+    fn apple_music_keeps_only_the_ends_of_plays() {
+        let csv = format!(
+            "{APPLE_HEADER}\n\
+             Artist,Song,Album,PLAY_END,2021-03-01T12:34:00Z,60000,TRACK_SKIPPED_FORWARDS,TRUE,false\n\
+             Artist,Song,Album,PLAY_START,2021-03-01T12:34:00Z,0,,,\n\
+             Artist,Song,Album,PLAY_END,2021-03-01T12:35:00Z,-5000,NATURAL_END_OF_TRACK,,\n\
+             Artist,Song,Album,PLAY_END,2021-03-01T12:36:00Z,,NATURAL_END_OF_TRACK,,\n"
+        );
+        assert!(is_apple_music_csv(csv.as_bytes()));
+        let history = parse_apple_music_activity(csv.as_bytes()).unwrap();
+        assert_eq!(history.report.format, HistoryFormat::AppleMusic);
+        assert_eq!(history.report.rows, 4);
+        assert_eq!(
+            skipped(&history),
+            [
+                (SkipReason::Malformed, 1),
+                (SkipReason::MissingMsPlayed, 1),
+                (SkipReason::NotAPlay, 1),
+            ]
+        );
+
+        let play = &history.records[0];
+        assert_eq!(play.timestamp, T);
+        assert_eq!(play.ms_played, 60_000);
+        assert_eq!(play.artist_name, "Artist");
+        assert_eq!(play.track_name, "Song");
+        assert_eq!(play.album_name.as_deref(), Some("Album"));
+        assert_eq!(play.skipped, Some(true));
+        assert_eq!(play.shuffle, Some(true));
+        assert_eq!(play.offline, Some(false));
+    }
+
+    #[test]
+    fn apple_music_without_a_start_counts_back_from_the_end() {
+        let csv = "Container Artist Name,Content Name,Event End Timestamp,\
+                   Play Duration Milliseconds\n\
+                   Artist,Song,2021-03-01T12:35:00Z,60000\n";
+        let history = parse_apple_music_activity(csv.as_bytes()).unwrap();
+        assert_eq!(history.records.len(), 1);
+        assert_eq!(history.records[0].timestamp, T);
+        assert_eq!(history.records[0].artist_name, "Artist");
+        assert_eq!(history.records[0].track_name, "Song");
+    }
+
+    #[test]
+    fn other_csvs_arent_apple_music() {
+        let csv = "Artist Name,Song Name\nArtist,Song\n";
+        assert!(!is_apple_music_csv(csv.as_bytes()));
+        assert!(parse_apple_music_activity(csv.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn youtube_keeps_only_youtube_music() {
+        let json = r#"[
+            {"header":"YouTube Music","title":"Watched Song",
+             "subtitles":[{"name":"Artist - Topic"}],"time":"2021-03-01T12:34:00.000Z"},
+            {"header":"YouTube","title":"Watched A Video",
+             "subtitles":[{"name":"Channel"}],"time":"2021-03-01T12:35:00.000Z"},
+            {"header":"YouTube Music","title":"Gesehen: Lied",
+             "subtitles":[{"name":"Band"}],"time":"2021-03-01T12:36:00.000Z"},
+            {"header":"YouTube Music","title":"Watched a video that has been removed",
+             "time":"2021-03-01T12:37:00.000Z"}
+        ]"#;
+        let history = parse_youtube_music_history(json.as_bytes(), 1000).unwrap();
+        assert_eq!(history.report.format, HistoryFormat::YoutubeMusic);
+        assert_eq!(history.report.rows, 4);
+        assert_eq!(
+            skipped(&history),
+            [
+                (SkipReason::MissingArtistName, 1),
+                (SkipReason::NotAPlay, 1),
+            ]
+        );
+
+        let names: Vec<(&str, &str)> = history
+            .records
+            .iter()
+            .map(|r| (r.artist_name.as_str(), r.track_name.as_str()))
+            .collect();
+        assert_eq!(names, [("Artist", "Song"), ("Band", "Gesehen: Lied")]);
+        assert_eq!(history.records[0].timestamp, T);
+        assert_eq!(history.records[1].timestamp, T + 2 * MINUTE);
+        assert!(history.records.iter().all(|r| r.ms_played == 1000));
+    }
+}
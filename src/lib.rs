@@ -1,15 +1,57 @@
-#![feature(vec_into_raw_parts)]
 #![allow(clippy::unused_unit)]
 
-mod triangulate;
+mod append;
+pub mod archive;
+pub mod binning;
+mod clip;
+mod color;
+mod curve;
+pub mod dataset;
+pub mod export;
+pub mod filter;
+pub mod import;
+pub mod merge;
+mod morph;
+pub mod normalize;
+pub mod parse;
+pub mod records;
+pub mod sessions;
+pub mod smooth;
+pub mod spans;
+pub mod stack;
+pub mod stats;
+pub mod streamgraph;
+pub mod triangulate;
 mod webgl;
+pub mod zone;
 
+use append::AppendableArea;
+use archive::read_history_zip;
+use clip::{clip_columns, clip_lines, clip_triangles, Bounds};
+use dataset::Dataset;
+use export::{export, ExportFormat, ExportValue};
+use import::{
+    is_apple_music_csv, parse_apple_music_activity, parse_lastfm_scrobbles,
+    parse_youtube_music_history, DEFAULT_ASSUMED_MS_PLAYED,
+};
+use merge::{merge_histories, DEFAULT_MERGE_TOLERANCE_MS};
+use morph::gen_morph;
+use parse::{parse_extended_history, parse_history, parse_regular_history};
+use records::{GroupBy, ListenRecord};
+use serde::Serialize;
+use sessions::{detect_sessions, SessionRules};
+use spans::ListenSpans;
+use stats::{listening_stats, StatsConfig};
 use std::fmt::Display;
-use triangulate::gen_mesh;
+use streamgraph::{Streamgraph, StreamgraphConfig};
+use svgtypes::PathSegment;
+use triangulate::{
+    flatten_area, mesh_columns, parse_path, SampleMode, DEFAULT_SAMPLES_PER_SEGMENT,
+};
 use wasm_bindgen::prelude::*;
 use webgl::WebglState;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Point {
     pub x: f64,
     pub y: f64,
@@ -22,11 +64,13 @@ impl Point {
 pub type Line = [Point; 2];
 pub type Triangle = [Point; 3]; // should always have ccw winding
 
+#[allow(unused_macros)]
 macro_rules! console_log {
     ($( $arg: expr ),*) => {
         web_sys::console::log_1(&JsValue::from_str(&format!("{}", format_args!($( $arg ),*) )))
     };
 }
+#[allow(unused_imports)]
 pub(crate) use console_log;
 
 trait ToJsError<T> {
@@ -38,24 +82,166 @@ impl<T, E: Display> ToJsError<T> for Result<T, E> {
     }
 }
 
+// maps become plain objects instead of js Maps
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsError> {
+    value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .to_jserr()
+}
+
 #[wasm_bindgen]
+#[derive(Clone)]
 pub struct TriangulatedArea {
     triangles: Vec<Triangle>,
     lines: Vec<Line>,
+    columns: Vec<Line>,
+    top_path: Vec<PathSegment>,
+    bot_path: Vec<PathSegment>,
+    sample_mode: SampleMode,
 }
 
 #[wasm_bindgen]
 impl TriangulatedArea {
     #[wasm_bindgen(constructor)]
     pub fn new(top_line: &str, bot_line: &str) -> Result<TriangulatedArea, JsError> {
-        let (triangles, lines) = gen_mesh(top_line, bot_line).to_jserr()?;
-        Ok(TriangulatedArea { triangles, lines })
+        Self::with_sampling(
+            top_line,
+            bot_line,
+            DEFAULT_SAMPLES_PER_SEGMENT,
+            SampleMode::Uniform,
+        )
+    }
+
+    pub fn with_sampling(
+        top_line: &str,
+        bot_line: &str,
+        samples_per_segment: i32,
+        sample_mode: SampleMode,
+    ) -> Result<TriangulatedArea, JsError> {
+        let top_path = parse_path(top_line).to_jserr()?;
+        let bot_path = parse_path(bot_line).to_jserr()?;
+        Self::from_paths(top_path, bot_path, samples_per_segment, sample_mode).to_jserr()
+    }
+
+    // re-tessellates only the part of the area inside of `ctx`'s viewport with more samples,
+    //   then clips the mesh exactly to the viewport's x-range (and y-range if `clip_y`)
+    pub fn clip_to_view(
+        &self,
+        ctx: &WebglCtx,
+        samples_per_segment: i32,
+        clip_y: bool,
+    ) -> Result<TriangulatedArea, JsError> {
+        if samples_per_segment <= 0 {
+            return Err(JsError::new("samples_per_segment must be positive"));
+        }
+        let view = ctx.internal.visible_bounds().to_jserr()?;
+        let bounds = if clip_y {
+            view
+        } else {
+            Bounds::x_range(view.x_min, view.x_max)
+        };
+
+        let columns = flatten_area(
+            &self.top_path,
+            &self.bot_path,
+            samples_per_segment,
+            self.sample_mode,
+            Some((bounds.x_min, bounds.x_max)),
+        )
+        .to_jserr()?;
+        // the segments either side of the view are sampled whole
+        let columns = clip_columns(&columns, &bounds);
+        let (triangles, lines) = mesh_columns(&columns);
+        Ok(TriangulatedArea {
+            triangles: clip_triangles(&triangles, &bounds),
+            lines: clip_lines(&lines, &bounds),
+            columns,
+            top_path: self.top_path.clone(),
+            bot_path: self.bot_path.clone(),
+            sample_mode: self.sample_mode,
+        })
+    }
+}
+
+impl TriangulatedArea {
+    pub fn from_paths(
+        top_path: Vec<PathSegment>,
+        bot_path: Vec<PathSegment>,
+        samples_per_segment: i32,
+        sample_mode: SampleMode,
+    ) -> Result<TriangulatedArea, String> {
+        if samples_per_segment <= 0 {
+            return Err("samples_per_segment must be positive".into());
+        }
+        let columns = flatten_area(&top_path, &bot_path, samples_per_segment, sample_mode, None)?;
+        let (triangles, lines) = mesh_columns(&columns);
+        Ok(TriangulatedArea {
+            triangles,
+            lines,
+            columns,
+            top_path,
+            bot_path,
+            sample_mode,
+        })
+    }
+
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+}
+
+#[wasm_bindgen]
+pub struct AreaMorph {
+    from: Vec<Triangle>,
+    to: Vec<Triangle>,
+}
+
+#[wasm_bindgen]
+impl AreaMorph {
+    #[wasm_bindgen(constructor)]
+    pub fn new(from: &TriangulatedArea, to: &TriangulatedArea) -> AreaMorph {
+        let morph = gen_morph(&from.columns, &to.columns);
+        AreaMorph {
+            from: morph.from,
+            to: morph.to,
+        }
+    }
+}
+
+// area built up one sample at a time, for data that's still coming in
+#[wasm_bindgen]
+pub struct LiveArea {
+    internal: AppendableArea,
+}
+
+#[wasm_bindgen]
+impl LiveArea {
+    #[wasm_bindgen(constructor)]
+    pub fn new(samples_per_segment: i32, sample_mode: SampleMode) -> Result<LiveArea, JsError> {
+        let internal = AppendableArea::new(samples_per_segment, sample_mode).to_jserr()?;
+        Ok(LiveArea { internal })
+    }
+
+    // x, y0 (bottom) and y1 (top) in canvas coordinates, x must not decrease
+    pub fn push(&mut self, x: f64, y0: f64, y1: f64) -> Result<(), JsError> {
+        self.internal.push(x, y0, y1).to_jserr()
+    }
+
+    // call once every context showing this area has been updated
+    pub fn mark_synced(&mut self) {
+        self.internal.mark_synced();
     }
 }
 
 #[wasm_bindgen]
 pub struct WebglCtx {
     internal: WebglState,
+    // times clear() was called, DatasetObjects from before the last one point at nothing
+    clears: usize,
 }
 
 #[wasm_bindgen]
@@ -63,19 +249,86 @@ impl WebglCtx {
     #[wasm_bindgen(constructor)]
     pub fn new(canvas_id: &str) -> Result<WebglCtx, JsError> {
         let internal = WebglState::init(canvas_id).to_jserr()?;
-        Ok(WebglCtx { internal })
+        Ok(WebglCtx {
+            internal,
+            clears: 0,
+        })
     }
 
     pub fn add_area(&mut self, area: &TriangulatedArea, color: &str) -> Result<(), JsError> {
-        let rgba = csscolorparser::parse(color).to_jserr()?.rgba_u8();
-        let color_rgb = [rgba.0, rgba.1, rgba.2];
-        let TriangulatedArea { triangles, lines } = area;
+        let color_rgb = css_to_rgb(color)?;
+        let TriangulatedArea {
+            triangles, lines, ..
+        } = area;
         self.internal
             .add_object(triangles, lines, color_rgb)
             .to_jserr()?;
         Ok(())
     }
 
+    pub fn add_morph(&mut self, morph: &AreaMorph, color: &str) -> Result<(), JsError> {
+        let color_rgb = css_to_rgb(color)?;
+        self.internal
+            .add_morph_object(&morph.from, &morph.to, color_rgb)
+            .to_jserr()?;
+        Ok(())
+    }
+
+    // blend parameter for every morph, 0 draws the `from` areas and 1 draws the `to` areas
+    pub fn set_morph_t(&mut self, t: f32) -> Result<(), JsError> {
+        self.internal.set_morph_t(t);
+        Ok(())
+    }
+
+    pub fn clear(&mut self) -> Result<(), JsError> {
+        self.internal.clear_objects();
+        self.clears += 1;
+        Ok(())
+    }
+
+    // returns the id to pass to update_live_area()
+    pub fn add_live_area(&mut self, area: &LiveArea, color: &str) -> Result<usize, JsError> {
+        let color_rgb = css_to_rgb(color)?;
+        self.internal
+            .add_dynamic_object(area.internal.triangles(), color_rgb)
+            .to_jserr()
+    }
+
+    // uploads the part of the area that changed since the last LiveArea::mark_synced()
+    pub fn update_live_area(&mut self, id: usize, area: &LiveArea) -> Result<(), JsError> {
+        if let Some(first_dirty) = area.internal.first_dirty_triangle() {
+            let tail = &area.internal.triangles()[first_dirty..];
+            self.internal
+                .update_dynamic_object(id, tail, first_dirty)
+                .to_jserr()?;
+        }
+        Ok(())
+    }
+
+    // one object per series, colored by key or by pick color
+    pub fn add_dataset(
+        &mut self,
+        dataset: &DatasetCtx,
+        picking: bool,
+    ) -> Result<DatasetObjects, JsError> {
+        let mut objects = DatasetObjects {
+            ids: Vec::new(),
+            picking,
+            clears: self.clears,
+        };
+        self.sync_dataset(&mut objects, dataset, true)?;
+        Ok(objects)
+    }
+
+    // rewrites the objects of series that changed since the last DatasetCtx::mark_synced()
+    pub fn update_dataset(
+        &mut self,
+        objects: &mut DatasetObjects,
+        dataset: &DatasetCtx,
+    ) -> Result<(), JsError> {
+        self.sync_dataset(objects, dataset, false)
+    }
+
     pub fn draw(&self) -> Result<(), JsError> {
         self.internal.draw_objects(false);
         Ok(())
@@ -95,11 +348,366 @@ impl WebglCtx {
     }
 }
 
+impl WebglCtx {
+    fn sync_dataset(
+        &mut self,
+        objects: &mut DatasetObjects,
+        dataset: &DatasetCtx,
+        everything: bool,
+    ) -> Result<(), JsError> {
+        // after a clear() every series is added again
+        if objects.clears != self.clears {
+            objects.ids.clear();
+            objects.clears = self.clears;
+        }
+        let dataset = &dataset.internal;
+        let graph = dataset.graph();
+        for (idx, (area, key)) in graph.areas.iter().zip(&graph.keys).enumerate() {
+            let color = if objects.picking {
+                &key.pick_color
+            } else {
+                &key.color
+            };
+            let color = css_to_rgb(color)?;
+            match objects.ids.get(idx) {
+                Some(&id) if everything || dataset.is_dirty(idx) => {
+                    self.internal
+                        .update_dynamic_object(id, area.triangles(), 0)
+                        .to_jserr()?;
+                    self.internal.set_object_color(id, color).to_jserr()?;
+                }
+                Some(_) => {}
+                None => {
+                    let id = self
+                        .internal
+                        .add_dynamic_object(area.triangles(), color)
+                        .to_jserr()?;
+                    objects.ids.push(id);
+                }
+            }
+        }
+        // series can disappear when keys get folded into "Other"
+        for &id in objects.ids.iter().skip(graph.areas.len()) {
+            self.internal.update_dynamic_object(id, &[], 0).to_jserr()?;
+        }
+        Ok(())
+    }
+}
+
+// the objects one WebglCtx draws a DatasetCtx with
+#[wasm_bindgen]
+pub struct DatasetObjects {
+    ids: Vec<usize>,
+    picking: bool,
+    // WebglCtx::clears when `ids` were made
+    clears: usize,
+}
+
+// a chart that more records can be added to, see dataset.rs
+#[wasm_bindgen]
+pub struct DatasetCtx {
+    internal: Dataset,
+}
+
+#[wasm_bindgen]
+impl DatasetCtx {
+    // `config` is a partial StreamgraphConfig
+    #[wasm_bindgen(constructor)]
+    pub fn new(config: JsValue) -> Result<DatasetCtx, JsError> {
+        let internal = Dataset::new(config_from_js(config)?).to_jserr()?;
+        Ok(DatasetCtx { internal })
+    }
+
+    // `records` is an array of TrackData, returns how many series changed
+    pub fn add_records(&mut self, records: JsValue) -> Result<usize, JsError> {
+        let records: Vec<ListenRecord> = serde_wasm_bindgen::from_value(records).to_jserr()?;
+        self.internal.add_records(&records).to_jserr()
+    }
+
+    // `records` is an array of TrackData, the chart ends up showing exactly those
+    // pass every record each time, e.g. the merged history after another upload, records
+    //   charted before that aren't in it are taken back out, returns how many series changed
+    pub fn replace_records(&mut self, records: JsValue) -> Result<usize, JsError> {
+        let records: Vec<ListenRecord> = serde_wasm_bindgen::from_value(records).to_jserr()?;
+        self.internal.replace_records(&records).to_jserr()
+    }
+
+    // call once every WebglCtx showing this dataset has been updated
+    pub fn mark_synced(&mut self) {
+        self.internal.mark_synced();
+    }
+
+    // array of KeyInfo, indexed the same way as the series
+    pub fn keys(&self) -> Result<JsValue, JsError> {
+        to_js(&self.internal.graph().keys)
+    }
+
+    // how many records each filter rule removed, over every batch
+    pub fn filter_report(&self) -> Result<JsValue, JsError> {
+        to_js(&self.internal.graph().filter_report)
+    }
+
+    pub fn folded_keys(&self) -> Result<JsValue, JsError> {
+        to_js(&self.internal.graph().folded_keys())
+    }
+
+    // csv or json of the binned and stacked series, `value` picks the wide csv's columns
+    pub fn export(
+        &self,
+        format: ExportFormat,
+        value: Option<ExportValue>,
+    ) -> Result<String, JsError> {
+        let value = value.unwrap_or(ExportValue::Score);
+        export(self.internal.graph(), format, value).to_jserr()
+    }
+
+    pub fn bin_edges(&self) -> Vec<f64> {
+        let binned = &self.internal.graph().binned;
+        binned.bin_edges.iter().map(|&edge| edge as f64).collect()
+    }
+
+    // raw (unstacked) score of every bin for the key at `key_idx`
+    pub fn scores(&self, key_idx: usize) -> Option<Vec<f64>> {
+        self.internal.graph().binned.scores.get(key_idx).cloned()
+    }
+
+    // canvas x of every bin
+    pub fn xs(&self) -> Vec<f64> {
+        self.internal.graph().xs.clone()
+    }
+
+    // canvas y of the bottom and top of the key at `key_idx` in every bin, as
+    //   [bottom..., top...], for drawing its outline
+    pub fn outline_ys(&self, key_idx: usize) -> Option<Vec<f64>> {
+        let graph = self.internal.graph();
+        let layer = graph.layers.get(key_idx)?;
+        let to_canvas = |y: &f64| graph.y_offset - y * graph.y_scale;
+        Some(layer.y0.iter().chain(&layer.y1).map(to_canvas).collect())
+    }
+
+    pub fn key_at_pixel(&self, rgba: u32) -> Option<String> {
+        let [r, g, b, _] = rgba.to_be_bytes();
+        self.internal
+            .graph()
+            .key_for_pick_color([r, g, b])
+            .map(|info| info.key.clone())
+    }
+}
+
+// a whole chart built from listening records in one call
+#[wasm_bindgen]
+pub struct StreamgraphCtx {
+    internal: Streamgraph,
+}
+
+#[wasm_bindgen]
+impl StreamgraphCtx {
+    // `records` is an array of TrackData, `config` is a partial StreamgraphConfig
+    #[wasm_bindgen(constructor)]
+    pub fn new(records: JsValue, config: JsValue) -> Result<StreamgraphCtx, JsError> {
+        let records: Vec<ListenRecord> = serde_wasm_bindgen::from_value(records).to_jserr()?;
+        let internal = Streamgraph::build(&records, config_from_js(config)?).to_jserr()?;
+        Ok(StreamgraphCtx { internal })
+    }
+
+    // array of KeyInfo, indexed the same way as the areas
+    pub fn keys(&self) -> Result<JsValue, JsError> {
+        to_js(&self.internal.keys)
+    }
+
+    // how many records each filter rule removed
+    pub fn filter_report(&self) -> Result<JsValue, JsError> {
+        to_js(&self.internal.filter_report)
+    }
+
+    // keys that were summed into the "Other" series, for its tooltip
+    pub fn folded_keys(&self) -> Result<JsValue, JsError> {
+        to_js(&self.internal.folded_keys())
+    }
+
+    pub fn bin_edges(&self) -> Vec<f64> {
+        self.internal
+            .binned
+            .bin_edges
+            .iter()
+            .map(|&edge| edge as f64)
+            .collect()
+    }
+
+    // raw (unstacked) score of every bin for the key at `key_idx`
+    pub fn scores(&self, key_idx: usize) -> Option<Vec<f64>> {
+        self.internal.binned.scores.get(key_idx).cloned()
+    }
+
+    // csv or json of the binned and stacked series along with the config and filter report,
+    //   `value` picks what the key columns of a wide csv hold
+    pub fn export(
+        &self,
+        format: ExportFormat,
+        value: Option<ExportValue>,
+    ) -> Result<String, JsError> {
+        let value = value.unwrap_or(ExportValue::Score);
+        export(&self.internal, format, value).to_jserr()
+    }
+
+    // draws every area in its key's color
+    pub fn add_to(&self, ctx: &mut WebglCtx) -> Result<(), JsError> {
+        for (area, key) in self.internal.areas.iter().zip(&self.internal.keys) {
+            ctx.add_area(area, &key.color)?;
+        }
+        Ok(())
+    }
+
+    // draws every area in its key's pick color, for hit testing with get_pixel()
+    pub fn add_picking_to(&self, ctx: &mut WebglCtx) -> Result<(), JsError> {
+        for (area, key) in self.internal.areas.iter().zip(&self.internal.keys) {
+            ctx.add_area(area, &key.pick_color)?;
+        }
+        Ok(())
+    }
+
+    // key under a pixel from get_pixel() on the picking canvas
+    pub fn key_at_pixel(&self, rgba: u32) -> Option<String> {
+        let [r, g, b, _] = rgba.to_be_bytes();
+        self.internal
+            .key_for_pick_color([r, g, b])
+            .map(|info| info.key.clone())
+    }
+}
+
+// StreamingHistoryN.json, returns { records: TrackData[], report }
+#[wasm_bindgen]
+pub fn parse_streaming_history(bytes: &[u8]) -> Result<JsValue, JsError> {
+    to_js(&parse_regular_history(bytes).to_jserr()?)
+}
+
+// endsong_N.json, returns { records: TrackData[], report }
+#[wasm_bindgen]
+pub fn parse_extended_streaming_history(bytes: &[u8]) -> Result<JsValue, JsError> {
+    to_js(&parse_extended_history(bytes).to_jserr()?)
+}
+
+// any streaming history file, the format is worked out from its contents
+// spotify, apple music and youtube music files are recognized, last.fm csvs aren't
+// returns { records: TrackData[], report }, or null if it isn't a streaming history
+#[wasm_bindgen]
+pub fn parse_any_streaming_history(bytes: &[u8]) -> Result<JsValue, JsError> {
+    match parse_history(bytes).to_jserr()? {
+        Some(parsed) => to_js(&parsed),
+        None => Ok(JsValue::NULL),
+    }
+}
+
+// my_spotify_data.zip or a google takeout as downloaded, one entry per history file found in it
+// returns { files: [{ name, records: TrackData[], report }], failed: [{ name, error }] }
+//   where `failed` has the entries that looked like a history but couldn't be read
+#[wasm_bindgen]
+pub fn parse_spotify_data_zip(bytes: &[u8]) -> Result<JsValue, JsError> {
+    to_js(&read_history_zip(bytes).to_jserr()?)
+}
+
+// scrobble csv from last.fm, returns { records: TrackData[], report }
+// scrobbles don't have a length, each one counts as `assumed_ms_played` (default 3.5 minutes)
+#[wasm_bindgen]
+pub fn parse_lastfm_scrobbles_csv(
+    bytes: &[u8],
+    assumed_ms_played: Option<f64>,
+) -> Result<JsValue, JsError> {
+    let assumed_ms_played = assumed_ms_played.map_or(DEFAULT_ASSUMED_MS_PLAYED, |ms| ms as u64);
+    to_js(&parse_lastfm_scrobbles(bytes, assumed_ms_played).to_jserr()?)
+}
+
+// true if `bytes` start with the header of apple's play activity csv, any other csv is taken to
+//   be last.fm scrobbles
+#[wasm_bindgen]
+pub fn is_apple_music_play_activity(bytes: &[u8]) -> bool {
+    is_apple_music_csv(bytes)
+}
+
+// Apple Music Play Activity.csv, returns { records: TrackData[], report }
+#[wasm_bindgen]
+pub fn parse_apple_music_play_activity(bytes: &[u8]) -> Result<JsValue, JsError> {
+    to_js(&parse_apple_music_activity(bytes).to_jserr()?)
+}
+
+// watch-history.json from google takeout, returns { records: TrackData[], report }
+// only youtube music plays are kept, each counts as `assumed_ms_played` (default 3.5 minutes)
+#[wasm_bindgen]
+pub fn parse_youtube_music_watch_history(
+    bytes: &[u8],
+    assumed_ms_played: Option<f64>,
+) -> Result<JsValue, JsError> {
+    let assumed_ms_played = assumed_ms_played.map_or(DEFAULT_ASSUMED_MS_PLAYED, |ms| ms as u64);
+    to_js(&parse_youtube_music_history(bytes, assumed_ms_played).to_jserr()?)
+}
+
+// both arguments are arrays of TrackData, returns { records: TrackData[], report }
+#[wasm_bindgen]
+pub fn merge_streaming_history(
+    extended: JsValue,
+    regular: JsValue,
+    tolerance_ms: Option<f64>,
+) -> Result<JsValue, JsError> {
+    let extended: Vec<ListenRecord> = serde_wasm_bindgen::from_value(extended).to_jserr()?;
+    let regular: Vec<ListenRecord> = serde_wasm_bindgen::from_value(regular).to_jserr()?;
+    let tolerance_ms = tolerance_ms.map_or(DEFAULT_MERGE_TOLERANCE_MS, |ms| ms as i64);
+    to_js(&merge_histories(&extended, &regular, tolerance_ms))
+}
+
+// `records` is an array of TrackData, returns { [key]: { firstListen, lastListen, firstLongListen } }
+//   where firstLongListen is the first play longer than `min_ms`
+#[wasm_bindgen]
+pub fn listen_spans(records: JsValue, group_by: GroupBy, min_ms: f64) -> Result<JsValue, JsError> {
+    let records: Vec<ListenRecord> = serde_wasm_bindgen::from_value(records).to_jserr()?;
+    let spans = ListenSpans::from_records(&records, group_by, min_ms.max(0.) as u64);
+    to_js(spans.spans())
+}
+
+// `records` is an array of TrackData, `config` is a partial StatsConfig
+// returns a ListeningStats object
+#[wasm_bindgen]
+pub fn listening_statistics(records: JsValue, config: JsValue) -> Result<JsValue, JsError> {
+    let records: Vec<ListenRecord> = serde_wasm_bindgen::from_value(records).to_jserr()?;
+    let config: StatsConfig = if config.is_undefined() || config.is_null() {
+        StatsConfig::default()
+    } else {
+        serde_wasm_bindgen::from_value(config).to_jserr()?
+    };
+    to_js(&listening_stats(&records, &config).to_jserr()?)
+}
+
+// `records` is an array of TrackData, `rules` is a partial SessionRules
+// returns { sessions: Session[], summary }
+#[wasm_bindgen]
+pub fn listening_sessions(records: JsValue, rules: JsValue) -> Result<JsValue, JsError> {
+    let records: Vec<ListenRecord> = serde_wasm_bindgen::from_value(records).to_jserr()?;
+    let rules: SessionRules = if rules.is_undefined() || rules.is_null() {
+        SessionRules::default()
+    } else {
+        serde_wasm_bindgen::from_value(rules).to_jserr()?
+    };
+    to_js(&detect_sessions(&records, rules))
+}
+
 #[wasm_bindgen(start)]
 pub fn wasm_init() {
     console_error_panic_hook::set_once();
 }
 
+// undefined or null gives the defaults
+fn config_from_js(config: JsValue) -> Result<StreamgraphConfig, JsError> {
+    if config.is_undefined() || config.is_null() {
+        Ok(StreamgraphConfig::default())
+    } else {
+        serde_wasm_bindgen::from_value(config).to_jserr()
+    }
+}
+
+fn css_to_rgb(color: &str) -> Result<[u8; 3], JsError> {
+    let rgba = csscolorparser::parse(color).to_jserr()?.rgba_u8();
+    Ok([rgba.0, rgba.1, rgba.2])
+}
+
 fn pack_rgba(rgba: [u8; 4]) -> u32 {
     let rgba = rgba.map(|subpx| subpx as u32);
     rgba[0] << 24 | rgba[1] << 16 | rgba[2] << 8 | rgba[3]
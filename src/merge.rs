@@ -0,0 +1,214 @@
+use crate::records::ListenRecord;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+// endTime in the regular history only has minute precision
+pub const DEFAULT_MERGE_TOLERANCE_MS: i64 = 90 * 1000;
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeReport {
+    // rows in the merged output from each source
+    pub from_extended: usize,
+    pub from_regular: usize,
+    // regular rows dropped because an extended row covers the same play
+    pub matched: usize,
+    // rows dropped because the exact same row was already seen, e.g. from overlapping exports
+    pub duplicates: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MergedHistory {
+    pub records: Vec<ListenRecord>,
+    pub report: MergeReport,
+}
+
+// plays in both histories are matched by track, artist, and end time within `tolerance_ms`,
+//   the extended row is kept since it has more in it
+// output is sorted by timestamp
+pub fn merge_histories(
+    extended: &[ListenRecord],
+    regular: &[ListenRecord],
+    tolerance_ms: i64,
+) -> MergedHistory {
+    let mut report = MergeReport::default();
+    let extended = dedup(extended, &mut report);
+    let regular = dedup(regular, &mut report);
+
+    // end times of the extended plays of every (track, artist), sorted
+    let mut extended_ends: HashMap<(&str, &str), Vec<(i64, bool)>> = HashMap::new();
+    for record in &extended {
+        extended_ends
+            .entry(match_key(record))
+            .or_default()
+            .push((end_time(record), false));
+    }
+    for ends in extended_ends.values_mut() {
+        ends.sort_unstable();
+    }
+
+    let mut records: Vec<ListenRecord> = extended.iter().map(|&r| r.clone()).collect();
+    report.from_extended = records.len();
+    let mut regular = regular;
+    regular.sort_by_key(|record| end_time(record));
+    for record in regular {
+        let end = end_time(record);
+        let matched = extended_ends.get_mut(&match_key(record)).and_then(|ends| {
+            let start = ends.partition_point(|&(other, _)| other < end - tolerance_ms);
+            ends[start..]
+                .iter_mut()
+                .take_while(|(other, _)| *other <= end + tolerance_ms)
+                .filter(|(_, used)| !used)
+                .min_by_key(|(other, _)| (other - end).abs())
+        });
+        match matched {
+            Some((_, used)) => {
+                *used = true;
+                report.matched += 1;
+            }
+            None => {
+                records.push(record.clone());
+                report.from_regular += 1;
+            }
+        }
+    }
+
+    records.sort_by_key(|record| record.timestamp);
+    MergedHistory { records, report }
+}
+
+fn dedup<'a>(records: &'a [ListenRecord], report: &mut MergeReport) -> Vec<&'a ListenRecord> {
+    let mut seen: HashSet<(i64, u64, &str, &str)> = HashSet::new();
+    records
+        .iter()
+        .filter(|record| {
+            let (track, artist) = match_key(record);
+            let is_new = seen.insert((record.timestamp, record.ms_played, track, artist));
+            if !is_new {
+                report.duplicates += 1;
+            }
+            is_new
+        })
+        .collect()
+}
+
+fn match_key(record: &ListenRecord) -> (&str, &str) {
+    (record.track_name.as_str(), record.artist_name.as_str())
+}
+
+fn end_time(record: &ListenRecord) -> i64 {
+    record.timestamp + record.ms_played as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{parse_extended_history, parse_regular_history, ParsedHistory};
+
+    fn extended_row(ts: &str, ms_played: u64, track: &str) -> String {
+        format!(
+            r#"{{"ts":"{}","ms_played":{},"master_metadata_track_name":"{}","master_metadata_album_artist_name":"artist","platform":"android"}}"#,
+            ts, ms_played, track
+        )
+    }
+
+    fn regular_row(end_time: &str, ms_played: u64, track: &str) -> String {
+        format!(
+            r#"{{"endTime":"{}","msPlayed":{},"trackName":"{}","artistName":"artist"}}"#,
+            end_time, ms_played, track
+        )
+    }
+
+    fn parse(
+        rows: &[String],
+        parser: fn(&[u8]) -> Result<ParsedHistory, String>,
+    ) -> Vec<ListenRecord> {
+        let json = format!("[{}]", rows.join(","));
+        parser(json.as_bytes()).unwrap().records
+    }
+
+    #[test]
+    fn plays_in_both_histories_are_kept_once() {
+        // europe's clocks went forward at 2021-03-28 01:00 utc, both exports are in utc so
+        //   the plays around it still line up
+        let extended = parse(
+            &[
+                extended_row("2021-03-27T23:59:50Z", 200_000, "a"),
+                extended_row("2021-03-28T00:59:40Z", 180_000, "b"),
+                extended_row("2021-03-28T01:02:37Z", 170_000, "c"),
+                extended_row("2021-10-31T01:00:20Z", 150_000, "d"),
+            ],
+            parse_extended_history,
+        );
+        let regular = parse(
+            &[
+                regular_row("2021-03-27 23:59", 200_000, "a"),
+                regular_row("2021-03-28 00:59", 180_000, "b"),
+                regular_row("2021-03-28 01:02", 170_000, "c"),
+                regular_row("2021-10-31 01:00", 150_000, "d"),
+                // only in the regular history
+                regular_row("2021-10-31 01:30", 120_000, "e"),
+            ],
+            parse_regular_history,
+        );
+
+        let merged = merge_histories(&extended, &regular, DEFAULT_MERGE_TOLERANCE_MS);
+        let tracks: Vec<&str> = merged
+            .records
+            .iter()
+            .map(|r| r.track_name.as_str())
+            .collect();
+        assert_eq!(tracks, ["a", "b", "c", "d", "e"]);
+        assert_eq!(merged.report.matched, 4);
+        assert_eq!(merged.report.from_extended, 4);
+        assert_eq!(merged.report.from_regular, 1);
+        // the extended rows win
+        assert!(merged.records[..4]
+            .iter()
+            .all(|r| r.platform.as_deref() == Some("android")));
+        assert_eq!(merged.records[4].platform, None);
+    }
+
+    #[test]
+    fn plays_further_apart_than_the_tolerance_are_both_kept() {
+        let extended = parse(
+            &[extended_row("2021-03-28T01:02:37Z", 60_000, "a")],
+            parse_extended_history,
+        );
+        let regular = parse(
+            &[regular_row("2021-03-28 02:02", 60_000, "a")],
+            parse_regular_history,
+        );
+
+        let merged = merge_histories(&extended, &regular, DEFAULT_MERGE_TOLERANCE_MS);
+        assert_eq!(merged.records.len(), 2);
+        assert_eq!(merged.report.matched, 0);
+    }
+
+    #[test]
+    fn each_extended_row_matches_one_regular_row() {
+        // the same track twice in a row, and an exact duplicate from an overlapping export
+        let extended = parse(
+            &[
+                extended_row("2021-01-01T12:01:10Z", 60_000, "a"),
+                extended_row("2021-01-01T12:02:15Z", 60_000, "a"),
+                extended_row("2021-01-01T12:02:15Z", 60_000, "a"),
+            ],
+            parse_extended_history,
+        );
+        let regular = parse(
+            &[
+                regular_row("2021-01-01 12:01", 60_000, "a"),
+                regular_row("2021-01-01 12:02", 60_000, "a"),
+                regular_row("2021-01-01 12:03", 60_000, "a"),
+            ],
+            parse_regular_history,
+        );
+
+        let merged = merge_histories(&extended, &regular, DEFAULT_MERGE_TOLERANCE_MS);
+        assert_eq!(merged.report.duplicates, 1);
+        assert_eq!(merged.report.matched, 2);
+        assert_eq!(merged.report.from_regular, 1);
+        assert_eq!(merged.records.len(), 3);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::triangulate::triangulate_columns;
+use crate::{Line, Point, Triangle};
+
+// two meshes with identical topology, vertex i of `from` blends into vertex i of `to`
+pub struct Morph {
+    pub from: Vec<Triangle>,
+    pub to: Vec<Triangle>,
+}
+
+// resamples both areas onto the union of their column x positions so every column
+//   exists in both meshes, then triangulates each side the same way
+pub fn gen_morph(from: &[Line], to: &[Line]) -> Morph {
+    let xs = merge_xs(from, to, f32::EPSILON as f64);
+
+    // an area with no samples grows out of / collapses into the other area's bottom line
+    let from_cols = match (from.is_empty(), to.is_empty()) {
+        (true, _) => resample_collapsed(to, &xs),
+        _ => resample(from, &xs),
+    };
+    let to_cols = match (to.is_empty(), from.is_empty()) {
+        (true, _) => resample_collapsed(from, &xs),
+        _ => resample(to, &xs),
+    };
+
+    Morph {
+        from: triangulate_columns(&from_cols),
+        to: triangulate_columns(&to_cols),
+    }
+}
+
+fn merge_xs(a: &[Line], b: &[Line], dist_thresh: f64) -> Vec<f64> {
+    let mut xs: Vec<f64> = a.iter().chain(b).map(|col| col[0].x).collect();
+    xs.sort_by(|x1, x2| x1.total_cmp(x2));
+    xs.dedup_by(|x2, x1| (*x2 - *x1).abs() <= dist_thresh);
+    xs
+}
+
+fn resample(columns: &[Line], xs: &[f64]) -> Vec<Line> {
+    let (top, bot): (Vec<Point>, Vec<Point>) = columns
+        .iter()
+        .map(|&[top_pt, bot_pt]| (top_pt, bot_pt))
+        .unzip();
+    xs.iter()
+        .map(|&x| [Point::new(x, y_at(&top, x)), Point::new(x, y_at(&bot, x))])
+        .collect()
+}
+
+fn resample_collapsed(columns: &[Line], xs: &[f64]) -> Vec<Line> {
+    let bot: Vec<Point> = columns.iter().map(|col| col[1]).collect();
+    xs.iter()
+        .map(|&x| {
+            let pt = Point::new(x, y_at(&bot, x));
+            [pt, pt]
+        })
+        .collect()
+}
+
+// linearly interpolates a polyline sorted by x, clamping to the ends outside of its range
+fn y_at(polyline: &[Point], x: f64) -> f64 {
+    let idx = polyline.partition_point(|pt| pt.x < x);
+    match (
+        idx.checked_sub(1).map(|i| polyline[i]),
+        polyline.get(idx).copied(),
+    ) {
+        (Some(p1), Some(p2)) if p2.x > p1.x => {
+            let t = (x - p1.x) / (p2.x - p1.x);
+            p1.y + (p2.y - p1.y) * t
+        }
+        (_, Some(p)) | (Some(p), None) => p.y,
+        (None, None) => 0.,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binning::TimeStep;
+    use crate::records::ListenRecord;
+    use crate::streamgraph::{Streamgraph, StreamgraphConfig};
+
+    const DAY: i64 = 24 * 60 * 60 * 1000;
+
+    fn play(artist: &str, day: i64) -> ListenRecord {
+        ListenRecord {
+            timestamp: day * DAY,
+            ms_played: 2 * 60 * 1000,
+            track_name: "t".into(),
+            artist_name: artist.into(),
+            ..Default::default()
+        }
+    }
+
+    fn layout(records: &[ListenRecord]) -> Streamgraph {
+        let config = StreamgraphConfig {
+            time_step: TimeStep::Day,
+            samples_per_segment: 4,
+            ..Default::default()
+        };
+        Streamgraph::build(records, config).unwrap()
+    }
+
+    // columns of `key`, none if the layout doesn't have it
+    fn columns<'a>(graph: &'a Streamgraph, key: &str) -> &'a [Line] {
+        graph
+            .keys
+            .iter()
+            .position(|info| info.key == key)
+            .map_or(&[], |idx| graph.areas[idx].columns.as_slice())
+    }
+
+    fn is_flat(tri: &Triangle) -> bool {
+        let [a, b, c] = tri;
+        ((b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y)).abs() < 1e-9
+    }
+
+    #[test]
+    fn series_morph_between_layouts_with_different_keys() {
+        // c is only in the second layout, b only in the first, and there's a day more of a
+        let first = layout(&[play("a", 0), play("b", 1), play("a", 2), play("b", 3)]);
+        let second = layout(&[
+            play("a", 0),
+            play("c", 1),
+            play("a", 2),
+            play("c", 3),
+            play("a", 4),
+        ]);
+
+        for key in ["a", "b", "c"] {
+            let (from, to) = (columns(&first, key), columns(&second, key));
+            let morph = gen_morph(from, to);
+            // the same vertices, one for one
+            assert_eq!(morph.from.len(), morph.to.len(), "{}", key);
+            assert!(!morph.from.is_empty());
+            for (from_tri, to_tri) in morph.from.iter().zip(&morph.to) {
+                for (from_pt, to_pt) in from_tri.iter().zip(to_tri) {
+                    assert_eq!(from_pt.x, to_pt.x);
+                }
+            }
+            // every column of both areas is in the morph
+            let xs: Vec<f64> = morph.to.iter().flatten().map(|pt| pt.x).collect();
+            for col in from.iter().chain(to) {
+                assert!(xs
+                    .iter()
+                    .any(|&x| (x - col[0].x).abs() <= f32::EPSILON as f64));
+            }
+        }
+
+        // c grows out of nothing and b shrinks away
+        assert!(gen_morph(columns(&first, "c"), columns(&second, "c"))
+            .from
+            .iter()
+            .all(is_flat));
+        assert!(gen_morph(columns(&first, "b"), columns(&second, "b"))
+            .to
+            .iter()
+            .all(is_flat));
+        assert!(!gen_morph(columns(&first, "a"), columns(&second, "a"))
+            .to
+            .iter()
+            .all(is_flat));
+    }
+
+    #[test]
+    fn resampling_interpolates_between_columns() {
+        let columns = [
+            [Point::new(0., 0.), Point::new(0., 10.)],
+            [Point::new(10., 4.), Point::new(10., 10.)],
+        ];
+        let resampled = resample(&columns, &[-5., 0., 2.5, 10., 20.]);
+        let ys: Vec<(f64, f64)> = resampled.iter().map(|[top, bot]| (top.y, bot.y)).collect();
+        // clamped outside the range
+        assert_eq!(ys, [(0., 10.), (0., 10.), (1., 10.), (4., 10.), (4., 10.)]);
+        let collapsed = resample_collapsed(&columns, &[5.]);
+        assert_eq!(collapsed, vec![[Point::new(5., 10.), Point::new(5., 10.)]]);
+    }
+}
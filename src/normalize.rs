@@ -0,0 +1,304 @@
+use crate::records::ListenRecord;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+// cut off with everything after them, matched ignoring ascii case
+const FEATURE_SEPARATORS: &[&str] = &[
+    " feat. ",
+    " feat ",
+    " ft. ",
+    " ft ",
+    " featuring ",
+    " & ",
+    " (feat",
+    " (ft",
+    " [feat",
+    " [ft",
+];
+
+// how artist names are cleaned up before records are grouped, everything is off by default
+// names that end up the same are one artist, shown under the spelling seen first
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ArtistNormalization {
+    // unicode compatibility form, full-width letters and ligatures become plain ones
+    pub nfkc: bool,
+    pub fold_case: bool,
+    // "Beyoncé" and "Beyonce" are the same artist
+    pub strip_diacritics: bool,
+    // "A feat. B", "A ft. B" and "A & B" count as plays of A
+    pub split_featured: bool,
+    // name -> the name to show instead, looked up after the rules above so "beyonce knowles"
+    //   catches "Beyoncé Knowles" if they're on
+    // the full name is looked up before featured artists are split off, so an alias for
+    //   "Simon & Garfunkel" keeps it whole
+    pub aliases: HashMap<String, String>,
+}
+
+impl ArtistNormalization {
+    pub fn is_noop(&self) -> bool {
+        !(self.nfkc || self.fold_case || self.strip_diacritics || self.split_featured)
+            && self.aliases.is_empty()
+    }
+}
+
+// remembers the first spelling of every artist, so it has to be kept around for records
+//   added later to land on the same names
+#[derive(Debug, Clone, Default)]
+pub struct ArtistNormalizer {
+    rules: ArtistNormalization,
+    // compared form of every alias -> shown name
+    aliases: HashMap<String, String>,
+    // compared form -> shown name
+    names: HashMap<String, String>,
+}
+
+impl ArtistNormalizer {
+    pub fn new(rules: &ArtistNormalization) -> Self {
+        let mut normalizer = Self {
+            rules: rules.clone(),
+            ..Default::default()
+        };
+        for (alias, name) in &rules.aliases {
+            let name = normalizer.clean(name);
+            let alias = normalizer.compared_form(&normalizer.clean(alias));
+            normalizer.aliases.insert(alias, name.clone());
+            // an alias target beats whatever spelling of it shows up first
+            let name_form = normalizer.compared_form(&name);
+            normalizer.names.insert(name_form, name);
+        }
+        normalizer
+    }
+
+    // the name `artist` is shown under
+    pub fn normalize(&mut self, artist: &str) -> String {
+        let artist = self.clean(artist);
+        let shown = match self.aliases.get(&self.compared_form(&artist)) {
+            Some(name) => name.clone(),
+            None if self.rules.split_featured => {
+                let main = main_artist(&artist);
+                let alias = self.aliases.get(&self.compared_form(main));
+                alias.cloned().unwrap_or_else(|| main.to_string())
+            }
+            None => artist,
+        };
+        let form = self.compared_form(&shown);
+        self.names.entry(form).or_insert(shown).clone()
+    }
+
+    // copies of `records` with normalized artist names, or `records` itself if nothing would
+    //   change
+    pub fn normalize_all<'a>(&mut self, records: &'a [ListenRecord]) -> Cow<'a, [ListenRecord]> {
+        if self.rules.is_noop() {
+            return Cow::Borrowed(records);
+        }
+        let records = records
+            .iter()
+            .map(|record| ListenRecord {
+                artist_name: self.normalize(&record.artist_name),
+                ..record.clone()
+            })
+            .collect();
+        Cow::Owned(records)
+    }
+
+    // nfkc and whitespace, the parts that are fine to show
+    fn clean(&self, name: &str) -> String {
+        let name: Cow<str> = if self.rules.nfkc {
+            Cow::Owned(name.nfkc().collect())
+        } else {
+            Cow::Borrowed(name)
+        };
+        name.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    // what names are compared by
+    fn compared_form(&self, name: &str) -> String {
+        let mut form = name.to_string();
+        if self.rules.strip_diacritics {
+            form = form
+                .nfd()
+                .filter(|&c| !is_combining_mark(c))
+                .nfc()
+                .collect();
+        }
+        if self.rules.fold_case {
+            form = form.to_lowercase();
+        }
+        form
+    }
+}
+
+// `artist` up to the first featured artist separator
+fn main_artist(artist: &str) -> &str {
+    // ascii lowercasing keeps byte offsets the same
+    let lower = artist.to_ascii_lowercase();
+    let cut = FEATURE_SEPARATORS
+        .iter()
+        .filter_map(|sep| lower.find(sep))
+        .min();
+    match cut {
+        Some(0) | None => artist,
+        Some(cut) => artist[..cut].trim_end(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> ArtistNormalization {
+        ArtistNormalization::default()
+    }
+
+    fn normalize_each(rules: &ArtistNormalization, names: &[&str]) -> Vec<String> {
+        let mut normalizer = ArtistNormalizer::new(rules);
+        names
+            .iter()
+            .map(|name| normalizer.normalize(name))
+            .collect()
+    }
+
+    #[test]
+    fn nothing_changes_by_default() {
+        assert!(rules().is_noop());
+        let names = ["Ｂｊörk", "björk", "Bjork", "A feat. B"];
+        assert_eq!(normalize_each(&rules(), &names), names);
+
+        let records = [ListenRecord {
+            artist_name: "A feat. B".into(),
+            ..Default::default()
+        }];
+        let normalized = ArtistNormalizer::new(&rules()).normalize_all(&records);
+        assert!(matches!(normalized, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn nfkc_turns_compatibility_characters_into_plain_ones() {
+        let nfkc = ArtistNormalization {
+            nfkc: true,
+            ..rules()
+        };
+        // full-width letters, a ligature and a non-breaking space
+        assert_eq!(
+            normalize_each(&nfkc, &["Ｂｊörk", "ﬁsh\u{a0}  band", "Björk"]),
+            ["Björk", "fish band", "Björk"]
+        );
+    }
+
+    #[test]
+    fn names_differing_in_case_are_shown_the_way_they_were_first_seen() {
+        let fold_case = ArtistNormalization {
+            fold_case: true,
+            ..rules()
+        };
+        assert_eq!(
+            normalize_each(&fold_case, &["ABBA", "Abba", "abba", "Abbá"]),
+            ["ABBA", "ABBA", "ABBA", "Abbá"]
+        );
+    }
+
+    #[test]
+    fn stripping_diacritics() {
+        let strip = ArtistNormalization {
+            strip_diacritics: true,
+            ..rules()
+        };
+        assert_eq!(
+            normalize_each(&strip, &["Beyoncé", "Beyonce", "Beyonce\u{301}", "beyonce"]),
+            ["Beyoncé", "Beyoncé", "Beyoncé", "beyonce"]
+        );
+
+        let both = ArtistNormalization {
+            strip_diacritics: true,
+            fold_case: true,
+            ..rules()
+        };
+        assert_eq!(
+            normalize_each(&both, &["Motörhead", "MOTORHEAD"]),
+            ["Motörhead", "Motörhead"]
+        );
+    }
+
+    #[test]
+    fn main_artist_cuts_at_every_separator() {
+        let cases = [
+            ("A feat. B", "A"),
+            ("A Feat B", "A"),
+            ("A ft. B", "A"),
+            ("A FT B", "A"),
+            ("A featuring B", "A"),
+            ("A & B", "A"),
+            ("A (feat. B)", "A"),
+            ("A (ft. B)", "A"),
+            ("A [feat. B]", "A"),
+            ("A [Ft. B]", "A"),
+            // the first one wins
+            ("A & B feat. C", "A"),
+            ("A feat. B & C", "A"),
+            // not a separator without the spaces
+            ("Aft B", "Aft B"),
+            ("Feat", "Feat"),
+            ("A", "A"),
+        ];
+        for (artist, main) in cases {
+            assert_eq!(main_artist(artist), main, "{}", artist);
+        }
+        // a name that starts with one is left whole
+        assert_eq!(main_artist(" & B"), " & B");
+    }
+
+    #[test]
+    fn featured_artists_count_as_plays_of_the_main_one() {
+        let split = ArtistNormalization {
+            split_featured: true,
+            ..rules()
+        };
+        assert_eq!(
+            normalize_each(&split, &["Drake feat. Rihanna", "Drake", "Drake & Future"]),
+            ["Drake", "Drake", "Drake"]
+        );
+    }
+
+    #[test]
+    fn aliases_are_looked_up_before_featured_artists_are_split_off() {
+        let aliases = ArtistNormalization {
+            split_featured: true,
+            fold_case: true,
+            aliases: HashMap::from([
+                ("simon & garfunkel".into(), "Simon & Garfunkel".into()),
+                ("the weeknd".into(), "The Weeknd".into()),
+            ]),
+            ..rules()
+        };
+        assert_eq!(
+            normalize_each(
+                &aliases,
+                &[
+                    "SIMON & GARFUNKEL",
+                    "Simon feat. Garfunkel",
+                    // the main artist is looked up too
+                    "THE WEEKND feat. Daft Punk",
+                    "the weeknd",
+                ]
+            ),
+            ["Simon & Garfunkel", "Simon", "The Weeknd", "The Weeknd"]
+        );
+    }
+
+    #[test]
+    fn alias_targets_beat_the_first_spelling_seen() {
+        let aliases = ArtistNormalization {
+            fold_case: true,
+            aliases: HashMap::from([("prince and the revolution".into(), "Prince".into())]),
+            ..rules()
+        };
+        assert_eq!(
+            normalize_each(&aliases, &["PRINCE", "Prince and The Revolution"]),
+            ["Prince", "Prince"]
+        );
+    }
+}
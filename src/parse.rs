@@ -0,0 +1,572 @@
+use crate::import::{
+    is_apple_music_csv, parse_apple_music_activity, parse_youtube_music_history,
+    DEFAULT_ASSUMED_MS_PLAYED,
+};
+use crate::records::{ContentKind, ListenRecord};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::de::{Deserializer as _, IgnoredAny, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HistoryFormat {
+    // StreamingHistoryN.json, StreamingHistory_music_N.json, StreamingHistory_podcast_N.json
+    Regular,
+    // endsong_N.json, Streaming_History_Audio_*.json, Streaming_History_Video_*.json
+    Extended,
+    // scrobble csv from one of the last.fm export tools, see import.rs
+    LastFm,
+    // Apple Music Play Activity.csv from apple's data and privacy page
+    AppleMusic,
+    // watch-history.json from google takeout
+    YoutubeMusic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SkipReason {
+    // not an object, or a field has the wrong type
+    Malformed,
+    MissingTimestamp,
+    InvalidTimestamp,
+    MissingMsPlayed,
+    // neither a track nor an episode name
+    MissingTrackName,
+    // neither an artist nor a show name
+    MissingArtistName,
+    // an apple music event other than the end of a play, or a youtube video that wasn't
+    //   watched in youtube music
+    NotAPlay,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            Self::Malformed => "malformed entry",
+            Self::MissingTimestamp => "missing timestamp",
+            Self::InvalidTimestamp => "invalid timestamp",
+            Self::MissingMsPlayed => "missing ms played",
+            Self::MissingTrackName => "missing track name",
+            Self::MissingArtistName => "missing artist name",
+            Self::NotAPlay => "not a play",
+        };
+        f.write_str(reason)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParseReport {
+    pub format: HistoryFormat,
+    pub rows: usize,
+    pub parsed: usize,
+    pub skipped: BTreeMap<SkipReason, usize>,
+}
+
+impl ParseReport {
+    pub(crate) fn new(format: HistoryFormat) -> Self {
+        Self {
+            format,
+            rows: 0,
+            parsed: 0,
+            skipped: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn skip(&mut self, reason: SkipReason) {
+        *self.skipped.entry(reason).or_insert(0) += 1;
+    }
+
+    pub fn total_skipped(&self) -> usize {
+        self.skipped.values().sum()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ParsedHistory {
+    pub records: Vec<ListenRecord>,
+    pub report: ParseReport,
+}
+
+// music rows have artistName and trackName, podcast rows have podcastName and episodeName
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RegularRow {
+    end_time: Option<String>,
+    artist_name: Option<String>,
+    track_name: Option<String>,
+    podcast_name: Option<String>,
+    episode_name: Option<String>,
+    ms_played: Option<u64>,
+}
+
+// music rows have master_metadata_*, podcast and video rows have episode_*
+#[derive(Deserialize)]
+struct ExtendedRow {
+    ts: Option<String>,
+    ms_played: Option<u64>,
+    master_metadata_track_name: Option<String>,
+    master_metadata_album_artist_name: Option<String>,
+    master_metadata_album_album_name: Option<String>,
+    episode_name: Option<String>,
+    episode_show_name: Option<String>,
+    platform: Option<String>,
+    conn_country: Option<String>,
+    reason_start: Option<String>,
+    reason_end: Option<String>,
+    shuffle: Option<bool>,
+    offline: Option<bool>,
+    skipped: Option<bool>,
+    incognito_mode: Option<bool>,
+}
+
+// just enough of a row to tell the formats apart
+#[derive(Deserialize)]
+struct SniffRow {
+    ts: Option<IgnoredAny>,
+    ms_played: Option<IgnoredAny>,
+    #[serde(rename = "endTime")]
+    end_time: Option<IgnoredAny>,
+    #[serde(rename = "msPlayed")]
+    ms_played_camel: Option<IgnoredAny>,
+    header: Option<IgnoredAny>,
+    time: Option<IgnoredAny>,
+}
+
+// the format of a file going by its first entry, or its header row for csvs
+// None if it isn't a history, last.fm csvs usually have no header so they're never recognized
+pub fn sniff_format(bytes: &[u8]) -> Option<HistoryFormat> {
+    if is_apple_music_csv(bytes) {
+        return Some(HistoryFormat::AppleMusic);
+    }
+    let mut first_row: Option<&RawValue> = None;
+    // stopping after the first row makes the deserializer error on the rest, that's fine
+    let _ = for_each_row(bytes, |row| {
+        first_row = Some(row);
+        false
+    });
+    let row: SniffRow = serde_json::from_str(first_row?.get()).ok()?;
+    match row {
+        SniffRow {
+            ts: Some(_),
+            ms_played: Some(_),
+            ..
+        } => Some(HistoryFormat::Extended),
+        SniffRow {
+            end_time: Some(_),
+            ms_played_camel: Some(_),
+            ..
+        } => Some(HistoryFormat::Regular),
+        SniffRow {
+            header: Some(_),
+            time: Some(_),
+            ..
+        } => Some(HistoryFormat::YoutubeMusic),
+        _ => None,
+    }
+}
+
+// Ok(None) if the file isn't a history sniff_format() recognizes
+// youtube plays get DEFAULT_ASSUMED_MS_PLAYED, the export doesn't say how long they were
+pub fn parse_history(bytes: &[u8]) -> Result<Option<ParsedHistory>, String> {
+    match sniff_format(bytes) {
+        Some(HistoryFormat::Regular) => parse_regular_history(bytes).map(Some),
+        Some(HistoryFormat::Extended) => parse_extended_history(bytes).map(Some),
+        Some(HistoryFormat::AppleMusic) => parse_apple_music_activity(bytes).map(Some),
+        Some(HistoryFormat::YoutubeMusic) => {
+            parse_youtube_music_history(bytes, DEFAULT_ASSUMED_MS_PLAYED).map(Some)
+        }
+        Some(HistoryFormat::LastFm) | None => Ok(None),
+    }
+}
+
+pub fn parse_regular_history(bytes: &[u8]) -> Result<ParsedHistory, String> {
+    parse_rows(bytes, HistoryFormat::Regular, |row: RegularRow| {
+        let ms_played = row.ms_played.ok_or(SkipReason::MissingMsPlayed)?;
+        let end_time = parse_timestamp(&row.end_time.ok_or(SkipReason::MissingTimestamp)?)
+            .map_err(|_| SkipReason::InvalidTimestamp)?;
+        let (track_name, artist_name, kind) = match (row.track_name, row.episode_name) {
+            (Some(track_name), _) => (track_name, row.artist_name, ContentKind::Music),
+            (None, Some(episode_name)) => (episode_name, row.podcast_name, ContentKind::Podcast),
+            (None, None) => return Err(SkipReason::MissingTrackName),
+        };
+        Ok(ListenRecord {
+            timestamp: end_time - ms_played as i64,
+            ms_played,
+            track_name,
+            artist_name: artist_name.ok_or(SkipReason::MissingArtistName)?,
+            album_name: None,
+            kind,
+            ..Default::default()
+        })
+    })
+}
+
+pub fn parse_extended_history(bytes: &[u8]) -> Result<ParsedHistory, String> {
+    parse_rows(bytes, HistoryFormat::Extended, |row: ExtendedRow| {
+        let ms_played = row.ms_played.ok_or(SkipReason::MissingMsPlayed)?;
+        let end_time = parse_timestamp(&row.ts.ok_or(SkipReason::MissingTimestamp)?)
+            .map_err(|_| SkipReason::InvalidTimestamp)?;
+        let (track_name, artist_name, album_name, kind) =
+            match (row.master_metadata_track_name, row.episode_name) {
+                (Some(track_name), _) => (
+                    track_name,
+                    row.master_metadata_album_artist_name,
+                    row.master_metadata_album_album_name,
+                    ContentKind::Music,
+                ),
+                (None, Some(episode_name)) => (
+                    episode_name,
+                    row.episode_show_name,
+                    None,
+                    ContentKind::Podcast,
+                ),
+                (None, None) => return Err(SkipReason::MissingTrackName),
+            };
+        Ok(ListenRecord {
+            timestamp: end_time - ms_played as i64,
+            ms_played,
+            track_name,
+            artist_name: artist_name.ok_or(SkipReason::MissingArtistName)?,
+            album_name,
+            kind,
+            platform: row.platform,
+            conn_country: row.conn_country,
+            reason_start: row.reason_start,
+            reason_end: row.reason_end,
+            shuffle: row.shuffle,
+            offline: row.offline,
+            skipped: row.skipped,
+            incognito_mode: row.incognito_mode,
+        })
+    })
+}
+
+// each row is deserialized on its own so one bad entry only skips that entry
+pub(crate) fn parse_rows<'de, Row: Deserialize<'de>>(
+    bytes: &'de [u8],
+    format: HistoryFormat,
+    to_record: impl Fn(Row) -> Result<ListenRecord, SkipReason>,
+) -> Result<ParsedHistory, String> {
+    let mut report = ParseReport::new(format);
+    let mut records: Vec<ListenRecord> = Vec::new();
+    for_each_row(bytes, |raw_row| {
+        report.rows += 1;
+        match serde_json::from_str::<Row>(raw_row.get())
+            .map_err(|_| SkipReason::Malformed)
+            .and_then(&to_record)
+        {
+            Ok(record) => {
+                report.parsed += 1;
+                records.push(record);
+            }
+            Err(reason) => report.skip(reason),
+        }
+        true
+    })?;
+    Ok(ParsedHistory { records, report })
+}
+
+// walks the top level array without collecting it, handing out the raw json of each element
+//   until `on_row` returns false
+pub fn for_each_row<'de>(
+    bytes: &'de [u8],
+    on_row: impl FnMut(&'de RawValue) -> bool,
+) -> Result<(), String> {
+    struct RowVisitor<F>(F);
+    impl<'de, F: FnMut(&'de RawValue) -> bool> Visitor<'de> for RowVisitor<F> {
+        type Value = ();
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an array of streaming history entries")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+            while let Some(row) = seq.next_element::<&'de RawValue>()? {
+                if !(self.0)(row) {
+                    break;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    deserializer
+        .deserialize_seq(RowVisitor(on_row))
+        .and_then(|_| deserializer.end())
+        .map_err(|e| format!("Invalid streaming history: {}", e))
+}
+
+// ms since epoch, accepts the same formats as js Date.parse() does for spotify's exports:
+//   "2021-03-01T12:34:56Z" (ts) and "2021-03-01 12:34" (endTime)
+// times without an offset are utc, which is what spotify uses for endTime
+pub fn parse_timestamp(timestamp: &str) -> Result<i64, String> {
+    let timestamp = timestamp.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(timestamp) {
+        return Ok(dt.timestamp_millis());
+    }
+    for format in [
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%dT%H:%M:%S%.f",
+    ] {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(timestamp, format) {
+            return Ok(dt.and_utc().timestamp_millis());
+        }
+    }
+    NaiveDate::parse_from_str(timestamp, "%Y-%m-%d")
+        .map(|date| {
+            date.and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+                .timestamp_millis()
+        })
+        .map_err(|_| format!("Invalid timestamp {:?}", timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: i64 = 60 * 1000;
+    // 2021-03-01T12:34:00Z
+    const T: i64 = 1_614_602_040_000;
+
+    fn skipped(report: &ParseReport) -> Vec<(SkipReason, usize)> {
+        report
+            .skipped
+            .iter()
+            .map(|(&reason, &n)| (reason, n))
+            .collect()
+    }
+
+    #[test]
+    fn regular_rows() {
+        let json = r#"[
+            {"endTime":"2021-03-01 12:34","artistName":"a","trackName":"t","msPlayed":60000},
+            {"endTime":"2021-03-01 12:34","podcastName":"show","episodeName":"ep","msPlayed":1000},
+            {"artistName":"a","trackName":"t","msPlayed":60000},
+            {"endTime":"yesterday","artistName":"a","trackName":"t","msPlayed":60000},
+            {"endTime":"2021-03-01 12:34","artistName":"a","trackName":"t"},
+            {"endTime":"2021-03-01 12:34","artistName":"a","msPlayed":60000},
+            {"endTime":"2021-03-01 12:34","trackName":"t","msPlayed":60000},
+            {"endTime":"2021-03-01 12:34","artistName":"a","trackName":"t","msPlayed":"long"},
+            "not an object"
+        ]"#;
+        let parsed = parse_regular_history(json.as_bytes()).unwrap();
+        assert_eq!(parsed.report.format, HistoryFormat::Regular);
+        assert_eq!(parsed.report.rows, 9);
+        assert_eq!(parsed.report.parsed, 2);
+        assert_eq!(
+            skipped(&parsed.report),
+            [
+                (SkipReason::Malformed, 2),
+                (SkipReason::MissingTimestamp, 1),
+                (SkipReason::InvalidTimestamp, 1),
+                (SkipReason::MissingMsPlayed, 1),
+                (SkipReason::MissingTrackName, 1),
+                (SkipReason::MissingArtistName, 1),
+            ]
+        );
+        assert_eq!(parsed.report.total_skipped(), 7);
+
+        // endTime is when the play ended
+        let music = &parsed.records[0];
+        assert_eq!(music.timestamp, T - MINUTE);
+        assert_eq!(
+            (music.artist_name.as_str(), music.track_name.as_str()),
+            ("a", "t")
+        );
+        assert_eq!(music.kind, ContentKind::Music);
+        let podcast = &parsed.records[1];
+        assert_eq!(
+            (podcast.artist_name.as_str(), podcast.track_name.as_str()),
+            ("show", "ep")
+        );
+        assert_eq!(podcast.kind, ContentKind::Podcast);
+    }
+
+    #[test]
+    fn extended_rows() {
+        let json = r#"[
+            {"ts":"2021-03-01T12:34:00Z","ms_played":60000,"master_metadata_track_name":"t",
+                "master_metadata_album_artist_name":"a","master_metadata_album_album_name":"al",
+                "platform":"android","reason_end":"fwdbtn","shuffle":true,"skipped":null,
+                "offline":false,"incognito_mode":false,"conn_country":"NL","reason_start":"clickrow"},
+            {"ts":"2021-03-01T12:34:00Z","ms_played":1000,"master_metadata_track_name":null,
+                "episode_name":"ep","episode_show_name":"show"},
+            {"ts":"2021-03-01T12:34:00Z","ms_played":1000,"master_metadata_track_name":null,
+                "episode_name":null},
+            {"ts":"2021-03-01T12:34:00Z","ms_played":1000,"master_metadata_track_name":"t"},
+            {"ts":null,"ms_played":1000,"master_metadata_track_name":"t"},
+            {"ts":"2021-03-01T12:34:00Z","ms_played":-5,"master_metadata_track_name":"t"}
+        ]"#;
+        let parsed = parse_extended_history(json.as_bytes()).unwrap();
+        assert_eq!(parsed.report.rows, 6);
+        assert_eq!(parsed.report.parsed, 2);
+        assert_eq!(
+            skipped(&parsed.report),
+            [
+                (SkipReason::Malformed, 1),
+                (SkipReason::MissingTimestamp, 1),
+                (SkipReason::MissingTrackName, 1),
+                (SkipReason::MissingArtistName, 1),
+            ]
+        );
+
+        let music = &parsed.records[0];
+        assert_eq!(music.timestamp, T - MINUTE);
+        assert_eq!(music.album_name.as_deref(), Some("al"));
+        assert_eq!(music.platform.as_deref(), Some("android"));
+        assert_eq!(music.conn_country.as_deref(), Some("NL"));
+        assert_eq!(music.reason_start.as_deref(), Some("clickrow"));
+        assert_eq!(music.shuffle, Some(true));
+        assert_eq!(music.offline, Some(false));
+        assert_eq!(music.incognito_mode, Some(false));
+        assert_eq!(music.skipped, None);
+        assert!(music.is_skip());
+        let podcast = &parsed.records[1];
+        assert_eq!(podcast.kind, ContentKind::Podcast);
+        assert_eq!(
+            (podcast.artist_name.as_str(), podcast.album_name.as_deref()),
+            ("show", None)
+        );
+    }
+
+    #[test]
+    fn empty_and_invalid_files() {
+        let parsed = parse_regular_history(b" [ ] ").unwrap();
+        assert!(parsed.records.is_empty());
+        assert_eq!(parsed.report.rows, 0);
+        assert_eq!(parsed.report.total_skipped(), 0);
+
+        assert!(parse_regular_history(b"").is_err());
+        assert!(parse_regular_history(br#"{"endTime":"2021-03-01 12:34"}"#).is_err());
+        // rows before the end of a truncated file don't make it valid
+        assert!(parse_extended_history(br#"[{"ts":"2021-03-01T12:34:00Z"},{"ts""#).is_err());
+        assert!(parse_regular_history(b"[] []").is_err());
+    }
+
+    #[test]
+    fn timestamps_without_an_offset_are_utc() {
+        assert_eq!(parse_timestamp("2021-03-01 12:34"), Ok(T));
+        assert_eq!(parse_timestamp("2021-03-01T12:34"), Ok(T));
+        assert_eq!(parse_timestamp("2021-03-01 12:34:00"), Ok(T));
+        assert_eq!(parse_timestamp("2021-03-01T12:34:00.250"), Ok(T + 250));
+        assert_eq!(parse_timestamp(" 2021-03-01T12:34:00Z "), Ok(T));
+        assert_eq!(
+            parse_timestamp("2021-03-01"),
+            Ok(T - (12 * 60 + 34) * MINUTE)
+        );
+    }
+
+    #[test]
+    fn timestamps_with_an_offset() {
+        assert_eq!(parse_timestamp("2021-03-01T13:34:00+01:00"), Ok(T));
+        assert_eq!(parse_timestamp("2021-03-01T07:34:00-05:00"), Ok(T));
+        assert_eq!(parse_timestamp("2021-03-01T12:34:00.5Z"), Ok(T + 500));
+        assert!(parse_timestamp("2021-03-01T12:34:00+25:00").is_err());
+        assert!(parse_timestamp("01/03/2021 12:34").is_err());
+        assert!(parse_timestamp("").is_err());
+    }
+
+    const APPLE_CSV: &str =
+        "Event Type,Song Name,Artist Name,Play Duration Milliseconds,Event Start Timestamp
+PLAY_END,t,a,60000,2021-03-01T12:33:00Z
+";
+
+    #[test]
+    fn formats_are_sniffed_from_the_first_row() {
+        let cases: [(&str, Option<HistoryFormat>); 9] = [
+            (
+                r#"[{"endTime":"2021-03-01 12:34","msPlayed":1,"artistName":"a","trackName":"t"}]"#,
+                Some(HistoryFormat::Regular),
+            ),
+            (
+                r#"[{"endTime":"2021-03-01 12:34","msPlayed":1,"podcastName":"s","episodeName":"e"}]"#,
+                Some(HistoryFormat::Regular),
+            ),
+            (
+                r#"  [{"ts":"2021-03-01T12:34:00Z","ms_played":1}]"#,
+                Some(HistoryFormat::Extended),
+            ),
+            (
+                r#"[{"header":"YouTube Music","title":"Watched t","time":"2021-03-01T12:34:00Z"}]"#,
+                Some(HistoryFormat::YoutubeMusic),
+            ),
+            (APPLE_CSV, Some(HistoryFormat::AppleMusic)),
+            // last.fm csvs are only read when asked for
+            ("a,al,t,1614602040\n", None),
+            ("[]", None),
+            (r#"{"endTime":"2021-03-01 12:34","msPlayed":1}"#, None),
+            // only the first row counts, a later one in another format doesn't change it
+            (
+                r#"[{"title":"x"},{"ts":"2021-03-01T12:34:00Z","ms_played":1}]"#,
+                None,
+            ),
+        ];
+        for (contents, format) in cases {
+            assert_eq!(sniff_format(contents.as_bytes()), format, "{}", contents);
+        }
+    }
+
+    #[test]
+    fn any_history_is_parsed_in_its_own_format() {
+        let parse = |contents: &str| parse_history(contents.as_bytes()).unwrap().unwrap();
+
+        let regular = parse(
+            r#"[{"endTime":"2021-03-01 12:34","msPlayed":60000,"artistName":"a","trackName":"t"}]"#,
+        );
+        let extended = parse(
+            r#"[{"ts":"2021-03-01T12:34:00Z","ms_played":60000,"master_metadata_track_name":"t",
+                "master_metadata_album_artist_name":"a"}]"#,
+        );
+        let apple = parse(APPLE_CSV);
+        let youtube = parse(
+            r#"[{"header":"YouTube Music","title":"Watched t","subtitles":[{"name":"a - Topic"}],
+                "time":"2021-03-01T12:33:00Z"}]"#,
+        );
+        for (parsed, format) in [
+            (&regular, HistoryFormat::Regular),
+            (&extended, HistoryFormat::Extended),
+            (&apple, HistoryFormat::AppleMusic),
+            (&youtube, HistoryFormat::YoutubeMusic),
+        ] {
+            assert_eq!(parsed.report.format, format);
+            assert_eq!(parsed.records.len(), 1);
+            let record = &parsed.records[0];
+            assert_eq!(
+                (record.artist_name.as_str(), record.track_name.as_str()),
+                ("a", "t")
+            );
+            assert_eq!(record.timestamp, T - MINUTE);
+        }
+        assert_eq!(youtube.records[0].ms_played, DEFAULT_ASSUMED_MS_PLAYED);
+    }
+
+    #[test]
+    fn files_that_arent_a_history_are_left_alone() {
+        for contents in [
+            "",
+            "[]",
+            "not json",
+            r#"[{"some":"thing else"},{"ts":"2021-03-01T12:34:00Z","ms_played":1}]"#,
+            "[1, 2, 3]",
+            "artist,album,track,date\na,al,t,1614602040\n",
+        ] {
+            assert!(
+                parse_history(contents.as_bytes()).unwrap().is_none(),
+                "{}",
+                contents
+            );
+        }
+        // recognized, but broken further on
+        let truncated = r#"[{"ts":"2021-03-01T12:34:00Z","ms_played":1},{"ts":"#;
+        assert!(parse_history(truncated.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use crate::binning::DataPoint;
+use crate::zone::Zone;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ContentKind {
+    #[default]
+    Music,
+    Podcast,
+}
+
+// one play of one track or episode, same shape as TrackData on the js side
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListenRecord {
+    // ms since unix epoch when the play started
+    pub timestamp: i64,
+    pub ms_played: u64,
+    // for podcasts, the episode name
+    pub track_name: String,
+    // for podcasts, the show name
+    pub artist_name: String,
+    #[serde(default)]
+    pub album_name: Option<String>,
+    #[serde(default)]
+    pub kind: ContentKind,
+    // everything below is only in the extended history
+    #[serde(default)]
+    pub platform: Option<String>,
+    #[serde(default)]
+    pub conn_country: Option<String>,
+    #[serde(default)]
+    pub reason_start: Option<String>,
+    #[serde(default)]
+    pub reason_end: Option<String>,
+    #[serde(default)]
+    pub shuffle: Option<bool>,
+    #[serde(default)]
+    pub offline: Option<bool>,
+    #[serde(default)]
+    pub skipped: Option<bool>,
+    #[serde(default)]
+    pub incognito_mode: Option<bool>,
+}
+
+impl ListenRecord {
+    // older exports leave `skipped` null, but a play ended by the next button is a skip too
+    pub fn is_skip(&self) -> bool {
+        self.skipped == Some(true) || self.reason_end.as_deref() == Some("fwdbtn")
+    }
+}
+
+// what splits records into separate streams
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum GroupBy {
+    Artist,
+    Album,
+    // "track - artist", so covers with the same name stay apart
+    Track,
+    // podcast show
+    Show,
+    // os family, the full platform string has device models and os versions in it
+    Platform,
+    Country,
+    ReasonStart,
+    ReasonEnd,
+    Shuffle,
+    Offline,
+}
+
+impl GroupBy {
+    // None if the record doesn't have the field, e.g. a podcast when grouping by album
+    pub fn key(&self, record: &ListenRecord) -> Option<String> {
+        let is_music = record.kind == ContentKind::Music;
+        match self {
+            Self::Artist => is_music.then(|| record.artist_name.clone()),
+            Self::Album => record.album_name.clone().filter(|_| is_music),
+            Self::Track => {
+                is_music.then(|| format!("{} - {}", record.track_name, record.artist_name))
+            }
+            Self::Show => (!is_music).then(|| record.artist_name.clone()),
+            Self::Platform => record.platform.as_deref().map(platform_family),
+            Self::Country => record.conn_country.clone(),
+            Self::ReasonStart => record.reason_start.clone(),
+            Self::ReasonEnd => record.reason_end.clone(),
+            Self::Shuffle => record
+                .shuffle
+                .map(|shuffle| if shuffle { "shuffle on" } else { "shuffle off" }.into()),
+            Self::Offline => record
+                .offline
+                .map(|offline| if offline { "offline" } else { "online" }.into()),
+        }
+    }
+}
+
+// "Android OS 9 API 28 (samsung, SM-G960U)" -> "Android"
+pub(crate) fn platform_family(platform: &str) -> String {
+    let lower = platform.to_lowercase();
+    let family = [
+        ("android", "Android"),
+        ("ios", "iOS"),
+        ("windows", "Windows"),
+        ("os x", "macOS"),
+        ("osx", "macOS"),
+        ("macos", "macOS"),
+        ("linux", "Linux"),
+        ("web_player", "Web Player"),
+        ("webplayer", "Web Player"),
+        ("cast", "Cast"),
+        ("partner", "Partner"),
+    ]
+    .iter()
+    .find(|(prefix, _)| lower.starts_with(prefix));
+    match family {
+        Some((_, family)) => family.to_string(),
+        None => platform
+            .split_whitespace()
+            .next()
+            .unwrap_or(platform)
+            .to_string(),
+    }
+}
+
+// what a stream's thickness measures in each bin
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ScoreMode {
+    MsPlayed,
+    PlayCount,
+    UniqueTracks,
+    // days with at least one play, in the listener's time zone
+    UniqueDays,
+    // ms played as a percentage of everything played in the bin
+    ShareOfBin,
+    // listening sessions the key was played in, see sessions.rs
+    Sessions,
+}
+
+impl ScoreMode {
+    // ShareOfBin scores like MsPlayed, the binned series is normalized afterwards
+    // `session` is the start of the record's session, only Sessions needs it
+    pub fn data_point(
+        &self,
+        key: &str,
+        record: &ListenRecord,
+        zone: Zone,
+        session: Option<i64>,
+    ) -> Result<DataPoint, String> {
+        let ms_played = record.ms_played as f64;
+        let point = match self {
+            Self::MsPlayed | Self::ShareOfBin => DataPoint::new(key, ms_played, record.timestamp),
+            Self::PlayCount => DataPoint::new(key, 1., record.timestamp),
+            Self::UniqueTracks => DataPoint::new(key, 1., record.timestamp)
+                .with_distinct(format!("{} - {}", record.track_name, record.artist_name)),
+            Self::UniqueDays => DataPoint::new(key, 1., record.timestamp)
+                .with_distinct(zone.to_local(record.timestamp)?.date().to_string()),
+            Self::Sessions => {
+                let session = session.ok_or("Sessions score needs the record's session")?;
+                DataPoint::new(key, 1., record.timestamp).with_distinct(session.to_string())
+            }
+        };
+        Ok(point)
+    }
+}
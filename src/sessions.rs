@@ -0,0 +1,327 @@
+use crate::records::{platform_family, ListenRecord};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+const MINUTE: u64 = 60 * 1000;
+
+// what splits plays into listening sessions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SessionRules {
+    // longest pause between the end of one play and the start of the next within a session
+    pub gap_ms: u64,
+    // a play on another os family than the one before it starts a new session, plays
+    //   without a platform never do
+    pub split_on_platform: bool,
+}
+
+impl Default for SessionRules {
+    fn default() -> Self {
+        Self {
+            gap_ms: 30 * MINUTE,
+            split_on_platform: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    // start of the first play and end of the last one, ms since epoch
+    pub start: i64,
+    pub end: i64,
+    pub plays: usize,
+    pub ms_played: u64,
+    // track and artist, same as GroupBy::Track
+    pub distinct_tracks: usize,
+    pub distinct_artists: usize,
+    pub skips: usize,
+    // os family, None if it changed during the session or no play had one
+    pub platform: Option<String>,
+}
+
+impl Session {
+    pub fn duration_ms(&self) -> i64 {
+        self.end - self.start
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSummary {
+    pub sessions: usize,
+    pub mean_plays: f64,
+    pub mean_duration_ms: f64,
+    pub median_duration_ms: f64,
+    // index of the longest session
+    pub longest: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sessions {
+    // in order of start
+    pub sessions: Vec<Session>,
+    pub summary: SessionSummary,
+}
+
+pub fn detect_sessions(records: &[ListenRecord], rules: SessionRules) -> Sessions {
+    let records: Vec<&ListenRecord> = records.iter().collect();
+    let (sessions, _) = assign_sessions(&records, rules);
+    Sessions {
+        summary: summarize(&sessions),
+        sessions,
+    }
+}
+
+// sessions in order of start, and the index of the session each record is in, indexed like
+//   `records`, which can come in any order
+pub fn assign_sessions(
+    records: &[&ListenRecord],
+    rules: SessionRules,
+) -> (Vec<Session>, Vec<usize>) {
+    let mut order: Vec<usize> = (0..records.len()).collect();
+    order.sort_by_key(|&idx| records[idx].timestamp);
+
+    let mut sessions: Vec<Session> = Vec::new();
+    let mut session_of = vec![0; records.len()];
+    let mut tracks: HashSet<(&str, &str)> = HashSet::new();
+    let mut artists: HashSet<&str> = HashSet::new();
+    // platforms seen in the current session, and the one of the last play that had one
+    let mut platforms: HashSet<String> = HashSet::new();
+    let mut last_platform: Option<String> = None;
+
+    for idx in order {
+        let record = records[idx];
+        let platform = record.platform.as_deref().map(platform_family);
+        let end = record.timestamp + record.ms_played as i64;
+
+        let continues = sessions.last().is_some_and(|session| {
+            let gap = record.timestamp - session.end;
+            let platform_changed = rules.split_on_platform
+                && platform.is_some()
+                && last_platform.is_some()
+                && platform != last_platform;
+            gap <= rules.gap_ms as i64 && !platform_changed
+        });
+        if !continues {
+            close_session(sessions.last_mut(), &platforms);
+            sessions.push(Session {
+                start: record.timestamp,
+                end,
+                plays: 0,
+                ms_played: 0,
+                distinct_tracks: 0,
+                distinct_artists: 0,
+                skips: 0,
+                platform: None,
+            });
+            tracks.clear();
+            artists.clear();
+            platforms.clear();
+        }
+
+        let session = sessions.last_mut().unwrap();
+        session.end = session.end.max(end);
+        session.plays += 1;
+        session.ms_played += record.ms_played;
+        session.skips += record.is_skip() as usize;
+        tracks.insert((&record.track_name, &record.artist_name));
+        artists.insert(&record.artist_name);
+        session.distinct_tracks = tracks.len();
+        session.distinct_artists = artists.len();
+        if let Some(platform) = platform {
+            platforms.insert(platform.clone());
+            last_platform = Some(platform);
+        }
+        session_of[idx] = sessions.len() - 1;
+    }
+    close_session(sessions.last_mut(), &platforms);
+    (sessions, session_of)
+}
+
+fn close_session(session: Option<&mut Session>, platforms: &HashSet<String>) {
+    if let Some(session) = session {
+        if platforms.len() == 1 {
+            session.platform = platforms.iter().next().cloned();
+        }
+    }
+}
+
+fn summarize(sessions: &[Session]) -> SessionSummary {
+    if sessions.is_empty() {
+        return SessionSummary::default();
+    }
+    let n = sessions.len() as f64;
+    let mut durations: Vec<i64> = sessions.iter().map(Session::duration_ms).collect();
+    durations.sort_unstable();
+    let mid = durations.len() / 2;
+    let median = match durations.len() % 2 {
+        0 => (durations[mid - 1] + durations[mid]) as f64 / 2.,
+        _ => durations[mid] as f64,
+    };
+    SessionSummary {
+        sessions: sessions.len(),
+        mean_plays: sessions.iter().map(|s| s.plays).sum::<usize>() as f64 / n,
+        mean_duration_ms: durations.iter().sum::<i64>() as f64 / n,
+        median_duration_ms: median,
+        // the first one if tied
+        longest: (0..sessions.len())
+            .max_by_key(|&idx| (sessions[idx].duration_ms(), std::cmp::Reverse(idx))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2021-03-01T12:34:00Z
+    const T: i64 = 1_614_602_040_000;
+
+    fn play(start: i64, ms_played: u64, platform: Option<&str>) -> ListenRecord {
+        ListenRecord {
+            timestamp: T + start,
+            ms_played,
+            track_name: format!("t{}", start),
+            artist_name: "a".into(),
+            platform: platform.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    fn plays_per_session(sessions: &Sessions) -> Vec<usize> {
+        sessions.sessions.iter().map(|s| s.plays).collect()
+    }
+
+    #[test]
+    fn a_pause_of_exactly_the_gap_keeps_the_session_going() {
+        let rules = SessionRules {
+            gap_ms: 10 * MINUTE,
+            ..Default::default()
+        };
+        let len = 3 * MINUTE as i64;
+        let gap = rules.gap_ms as i64;
+        let records = [
+            play(0, 3 * MINUTE, None),
+            play(len + gap, 3 * MINUTE, None),
+            // a millisecond too late
+            play(2 * len + 2 * gap + 1, 3 * MINUTE, None),
+        ];
+        let sessions = detect_sessions(&records, rules);
+        assert_eq!(plays_per_session(&sessions), [2, 1]);
+        assert_eq!(sessions.sessions[0].start, T);
+        assert_eq!(sessions.sessions[0].end, T + 2 * len + gap);
+        assert_eq!(sessions.sessions[0].ms_played, 6 * MINUTE);
+    }
+
+    #[test]
+    fn overlapping_plays_dont_shorten_the_session() {
+        let records = [
+            play(0, 20 * MINUTE, None),
+            play(MINUTE as i64, MINUTE, None),
+        ];
+        let sessions = detect_sessions(&records, SessionRules::default());
+        assert_eq!(sessions.sessions[0].end, T + 20 * MINUTE as i64);
+    }
+
+    #[test]
+    fn records_are_assigned_in_any_order() {
+        let late = play(2 * 60 * MINUTE as i64, MINUTE, None);
+        let early = play(0, MINUTE, None);
+        let (sessions, session_of) = assign_sessions(&[&late, &early], SessionRules::default());
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].start, T);
+        assert_eq!(session_of, [1, 0]);
+    }
+
+    #[test]
+    fn switching_os_family_starts_a_session_when_asked() {
+        let minute = MINUTE as i64;
+        let records = [
+            play(0, MINUTE, Some("Android OS 11 API 30 (Google, Pixel 4a)")),
+            play(minute, MINUTE, Some("android")),
+            // no platform, doesn't split either way
+            play(2 * minute, MINUTE, None),
+            play(3 * minute, MINUTE, Some("iOS 14.4 (iPhone12,1)")),
+            play(4 * minute, MINUTE, None),
+            play(5 * minute, MINUTE, Some("ios")),
+        ];
+
+        let split = SessionRules {
+            split_on_platform: true,
+            ..Default::default()
+        };
+        let sessions = detect_sessions(&records, split);
+        assert_eq!(plays_per_session(&sessions), [3, 3]);
+        let platforms: Vec<Option<&str>> = sessions
+            .sessions
+            .iter()
+            .map(|s| s.platform.as_deref())
+            .collect();
+        assert_eq!(platforms, [Some("Android"), Some("iOS")]);
+
+        // one session on both, so no platform
+        let sessions = detect_sessions(&records, SessionRules::default());
+        assert_eq!(plays_per_session(&sessions), [6]);
+        assert_eq!(sessions.sessions[0].platform, None);
+    }
+
+    #[test]
+    fn distinct_tracks_artists_and_skips() {
+        let mut records = vec![play(0, MINUTE, None), play(0, MINUTE, None)];
+        records.push(ListenRecord {
+            artist_name: "b".into(),
+            reason_end: Some("fwdbtn".into()),
+            ..play(MINUTE as i64, MINUTE, None)
+        });
+        let session = &detect_sessions(&records, SessionRules::default()).sessions[0];
+        assert_eq!(session.plays, 3);
+        assert_eq!(session.distinct_tracks, 2);
+        assert_eq!(session.distinct_artists, 2);
+        assert_eq!(session.skips, 1);
+    }
+
+    fn session_lasting(minutes: i64) -> Session {
+        Session {
+            start: 0,
+            end: minutes * MINUTE as i64,
+            plays: 1,
+            ms_played: 0,
+            distinct_tracks: 1,
+            distinct_artists: 1,
+            skips: 0,
+            platform: None,
+        }
+    }
+
+    fn summary_of(minutes: &[i64]) -> SessionSummary {
+        let sessions: Vec<Session> = minutes.iter().map(|&m| session_lasting(m)).collect();
+        summarize(&sessions)
+    }
+
+    #[test]
+    fn median_of_odd_and_even_counts() {
+        let minute = MINUTE as f64;
+        let odd = summary_of(&[30, 10, 20]);
+        assert_eq!(odd.median_duration_ms, 20. * minute);
+        assert_eq!(odd.mean_duration_ms, 20. * minute);
+
+        let even = summary_of(&[40, 10, 20, 30]);
+        assert_eq!(even.median_duration_ms, 25. * minute);
+        assert_eq!(even.sessions, 4);
+        assert_eq!(even.mean_plays, 1.);
+
+        assert_eq!(summary_of(&[7]).median_duration_ms, 7. * minute);
+    }
+
+    #[test]
+    fn longest_is_the_first_of_a_tie() {
+        assert_eq!(summary_of(&[10, 30, 20, 30]).longest, Some(1));
+        assert_eq!(summary_of(&[5, 5, 5]).longest, Some(0));
+        assert_eq!(summary_of(&[5, 6]).longest, Some(1));
+
+        let none = summary_of(&[]);
+        assert_eq!(none.sessions, 0);
+        assert_eq!(none.longest, None);
+    }
+}
@@ -0,0 +1,354 @@
+use crate::binning::{BinnedSeries, DataPoint};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+const DAY: f64 = 24. * 60. * 60. * 1000.;
+// kernels are cut off where they're this small, what's cut off is spread over the rest
+const KERNEL_EPSILON: f64 = 1e-6;
+
+// every kind of smoothing keeps each key's total the same, the score in each bin is spread
+//   over its neighbours with weights that sum to 1 over the bins that exist
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Smoothing {
+    #[default]
+    None,
+    // centered window, `window` bins wide
+    MovingAverage {
+        window: usize,
+    },
+    // standard deviation in bins
+    Gaussian {
+        bandwidth: f64,
+    },
+    // each bin carries over 1 - alpha of its score into the following bins
+    Exponential {
+        alpha: f64,
+    },
+    // gaussian kernel density estimate on the plays themselves rather than on the bins
+    #[serde(rename_all = "camelCase")]
+    Kde {
+        bandwidth_days: f64,
+    },
+}
+
+// `points` are the ones `binned` was made from, only used for Smoothing::Kde
+pub fn smooth_binned(
+    binned: &mut BinnedSeries,
+    points: &[DataPoint],
+    smoothing: Smoothing,
+) -> Result<(), String> {
+    match smoothing {
+        Smoothing::None => {}
+        Smoothing::Kde { bandwidth_days } => {
+            if !is_positive(bandwidth_days) {
+                return Err("KDE bandwidth must be positive".into());
+            }
+            binned.scores = kde(binned, points, bandwidth_days * DAY);
+        }
+        _ => {
+            for series in binned.scores.iter_mut() {
+                *series = smooth_series(series, smoothing)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn smooth_series(series: &[f64], smoothing: Smoothing) -> Result<Vec<f64>, String> {
+    match smoothing {
+        Smoothing::None => Ok(series.to_vec()),
+        Smoothing::MovingAverage { window } => {
+            if window == 0 {
+                return Err("Moving average window must be at least 1".into());
+            }
+            let window = isize::try_from(window)
+                .map_err(|_| "Moving average window is too large".to_string())?;
+            // even windows lean forward
+            let back = (window - 1) / 2;
+            Ok(scatter(series, -back, window / 2, |_| 1.))
+        }
+        Smoothing::Gaussian { bandwidth } => {
+            if !is_positive(bandwidth) {
+                return Err("Gaussian bandwidth must be positive".into());
+            }
+            let reach = (bandwidth * (-2. * KERNEL_EPSILON.ln()).sqrt()).ceil() as isize;
+            let reach = reach.min(series.len() as isize);
+            Ok(scatter(series, -reach, reach, |offset| {
+                (-0.5 * (offset as f64 / bandwidth).powi(2)).exp()
+            }))
+        }
+        Smoothing::Exponential { alpha } => {
+            if !(alpha > 0. && alpha <= 1.) {
+                return Err("Exponential smoothing alpha must be in (0, 1]".into());
+            }
+            let reach = match alpha {
+                a if a >= 1. => 0,
+                a => (KERNEL_EPSILON.ln() / (1. - a).ln()).ceil() as isize,
+            };
+            let reach = reach.min(series.len() as isize);
+            Ok(scatter(series, 0, reach, |offset| {
+                (1. - alpha).powi(offset as i32)
+            }))
+        }
+        Smoothing::Kde { .. } => Err("KDE needs the raw plays, use smooth_binned".into()),
+    }
+}
+
+// spreads each bin over the bins `back..=forward` away from it (back <= 0), weights are
+//   normalized over the bins in range so nothing falls off the ends
+fn scatter(series: &[f64], back: isize, forward: isize, weight: impl Fn(isize) -> f64) -> Vec<f64> {
+    let n = series.len() as isize;
+    let mut out = vec![0.; series.len()];
+    for (src, &score) in series.iter().enumerate() {
+        if score == 0. {
+            continue;
+        }
+        let src = src as isize;
+        let offsets = (back.max(-src))..=(forward.min(n - 1 - src));
+        let total_weight: f64 = offsets.clone().map(&weight).sum();
+        for offset in offsets {
+            out[(src + offset) as usize] += score * weight(offset) / total_weight;
+        }
+    }
+    out
+}
+
+// each play is a gaussian centered on its timestamp, integrated over every bin
+fn kde(binned: &BinnedSeries, points: &[DataPoint], bandwidth_ms: f64) -> Vec<Vec<f64>> {
+    let mut scores = vec![vec![0.; binned.n_bins()]; binned.keys.len()];
+    let edges = &binned.bin_edges;
+    if edges.len() < 2 {
+        return scores;
+    }
+    let key_indices: HashMap<&str, usize> = binned
+        .keys
+        .iter()
+        .enumerate()
+        .map(|(idx, key)| (key.as_str(), idx))
+        .collect();
+    let reach = bandwidth_ms * (-2. * KERNEL_EPSILON.ln()).sqrt();
+    // same rule as bin_points, a distinct value only counts once per key and bin
+    let mut seen: HashSet<(usize, usize, &str)> = HashSet::new();
+    for point in points {
+        let key_idx = match key_indices.get(point.key.as_str()) {
+            Some(&idx) => idx,
+            None => continue,
+        };
+        if let Some(distinct) = &point.distinct {
+            let bin = edges.partition_point(|&edge| edge <= point.timestamp) - 1;
+            if !seen.insert((key_idx, bin, distinct)) {
+                continue;
+            }
+        }
+        let ts = point.timestamp as f64;
+        let first_bin = edges
+            .partition_point(|&edge| (edge as f64) <= ts - reach)
+            .saturating_sub(1);
+        let last_bin = edges
+            .partition_point(|&edge| (edge as f64) < ts + reach)
+            .clamp(first_bin + 1, edges.len() - 1);
+
+        let cdf = |edge: i64| normal_cdf((edge as f64 - ts) / bandwidth_ms);
+        let total_weight = cdf(edges[last_bin]) - cdf(edges[first_bin]);
+        if !is_positive(total_weight) {
+            // kernel too narrow to show up in f64, same as not smoothing
+            let bin = edges.partition_point(|&edge| edge <= point.timestamp) - 1;
+            scores[key_idx][bin.min(binned.n_bins() - 1)] += point.score;
+            continue;
+        }
+        for bin in first_bin..last_bin {
+            let weight = (cdf(edges[bin + 1]) - cdf(edges[bin])) / total_weight;
+            scores[key_idx][bin] += point.score * weight;
+        }
+    }
+    scores
+}
+
+fn is_positive(val: f64) -> bool {
+    val.is_finite() && val > 0.
+}
+
+fn normal_cdf(z: f64) -> f64 {
+    0.5 * (1. + erf(z / std::f64::consts::SQRT_2))
+}
+
+// Abramowitz & Stegun 7.1.26, max error 1.5e-7
+fn erf(x: f64) -> f64 {
+    let t = 1. / (1. + 0.3275911 * x.abs());
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let y = 1. - poly * (-x * x).exp();
+    y.copysign(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binning::{bin_points, TimeStep};
+    use crate::zone::Zone;
+
+    // most of the score sits in the first and last bins, where the kernels get cut off
+    fn series() -> Vec<f64> {
+        vec![5., 0., 1., 3., 0., 0., 2., 8.]
+    }
+
+    fn spike(n: usize, at: usize) -> Vec<f64> {
+        let mut series = vec![0.; n];
+        series[at] = 6.;
+        series
+    }
+
+    fn assert_total(smoothed: &[f64], expected: f64) {
+        let total: f64 = smoothed.iter().sum();
+        assert!(
+            (total - expected).abs() < 1e-9,
+            "{:?} sums to {}, not {}",
+            smoothed,
+            total,
+            expected
+        );
+    }
+
+    fn smoothed(series: &[f64], smoothing: Smoothing) -> Vec<f64> {
+        smooth_series(series, smoothing).unwrap()
+    }
+
+    #[test]
+    fn moving_average_keeps_totals() {
+        for window in 1..=10 {
+            let smoothing = Smoothing::MovingAverage { window };
+            assert_total(&smoothed(&series(), smoothing), 19.);
+            assert_total(&smoothed(&spike(8, 0), smoothing), 6.);
+            assert_total(&smoothed(&spike(8, 7), smoothing), 6.);
+        }
+        assert_eq!(
+            smoothed(&series(), Smoothing::MovingAverage { window: 1 }),
+            series()
+        );
+    }
+
+    #[test]
+    fn moving_average_spreads_over_the_window() {
+        let odd = Smoothing::MovingAverage { window: 3 };
+        assert_eq!(smoothed(&spike(5, 2), odd), [0., 2., 2., 2., 0.]);
+        // only two of the three bins exist at the edges
+        assert_eq!(smoothed(&spike(5, 0), odd), [3., 3., 0., 0., 0.]);
+        assert_eq!(smoothed(&spike(5, 4), odd), [0., 0., 0., 3., 3.]);
+
+        // even windows lean forward
+        let even = Smoothing::MovingAverage { window: 2 };
+        assert_eq!(smoothed(&spike(5, 2), even), [0., 0., 3., 3., 0.]);
+        assert_eq!(smoothed(&spike(5, 4), even), [0., 0., 0., 0., 6.]);
+    }
+
+    #[test]
+    fn gaussian_keeps_totals() {
+        for bandwidth in [0.1, 0.5, 1., 2.5, 50.] {
+            let smoothing = Smoothing::Gaussian { bandwidth };
+            assert_total(&smoothed(&series(), smoothing), 19.);
+            assert_total(&smoothed(&spike(8, 0), smoothing), 6.);
+            assert_total(&smoothed(&spike(8, 7), smoothing), 6.);
+        }
+    }
+
+    #[test]
+    fn gaussian_is_symmetric_away_from_the_edges() {
+        let out = smoothed(&spike(21, 10), Smoothing::Gaussian { bandwidth: 1.5 });
+        for offset in 1..=10 {
+            assert!((out[10 - offset] - out[10 + offset]).abs() < 1e-12);
+            assert!(out[10 - offset] <= out[10 - offset + 1]);
+        }
+    }
+
+    #[test]
+    fn exponential_keeps_totals_and_only_carries_forward() {
+        for alpha in [0.05, 0.3, 0.5, 0.99, 1.] {
+            let smoothing = Smoothing::Exponential { alpha };
+            assert_total(&smoothed(&series(), smoothing), 19.);
+            assert_total(&smoothed(&spike(8, 0), smoothing), 6.);
+            assert_total(&smoothed(&spike(8, 7), smoothing), 6.);
+            assert_eq!(smoothed(&spike(8, 3), smoothing)[..3], [0.; 3]);
+        }
+        let out = smoothed(&spike(3, 0), Smoothing::Exponential { alpha: 0.5 });
+        assert!((out[0] - 6. * 4. / 7.).abs() < 1e-12);
+        assert!((out[1] - 6. * 2. / 7.).abs() < 1e-12);
+        assert!((out[2] - 6. * 1. / 7.).abs() < 1e-12);
+        assert_eq!(
+            smoothed(&series(), Smoothing::Exponential { alpha: 1. }),
+            series()
+        );
+    }
+
+    #[test]
+    fn kde_keeps_totals() {
+        const START: i64 = 1_600_000_000_000;
+        let day = DAY as i64;
+        let points = vec![
+            DataPoint::new("a", 5., START),
+            DataPoint::new("a", 2., START + 3 * day + 1),
+            DataPoint::new("a", 4., START + 9 * day),
+            DataPoint::new("b", 7., START + day / 2),
+            DataPoint::new("b", 1., START + 9 * day + day / 2),
+        ];
+        for bandwidth_days in [0.01, 0.5, 2., 30.] {
+            let mut binned = bin_points(&points, TimeStep::Day, Zone::Utc).unwrap();
+            smooth_binned(&mut binned, &points, Smoothing::Kde { bandwidth_days }).unwrap();
+            assert_total(&binned.scores[0], 11.);
+            assert_total(&binned.scores[1], 8.);
+        }
+    }
+
+    #[test]
+    fn kde_with_a_tiny_bandwidth_is_the_same_as_binning() {
+        let points = vec![
+            DataPoint::new("a", 5., 1_600_000_000_000),
+            DataPoint::new("a", 2., 1_600_000_000_000 + 3 * DAY as i64),
+        ];
+        let raw = bin_points(&points, TimeStep::Day, Zone::Utc).unwrap();
+        let mut binned = raw.clone();
+        let smoothing = Smoothing::Kde {
+            bandwidth_days: 1e-12,
+        };
+        smooth_binned(&mut binned, &points, smoothing).unwrap();
+        assert_eq!(binned.scores, raw.scores);
+    }
+
+    #[test]
+    fn bad_parameters_are_rejected() {
+        let series = series();
+        let rejected = [
+            Smoothing::MovingAverage { window: 0 },
+            Smoothing::MovingAverage { window: usize::MAX },
+            Smoothing::Gaussian { bandwidth: 0. },
+            Smoothing::Gaussian { bandwidth: -1. },
+            Smoothing::Gaussian {
+                bandwidth: f64::NAN,
+            },
+            Smoothing::Gaussian {
+                bandwidth: f64::INFINITY,
+            },
+            Smoothing::Exponential { alpha: 0. },
+            Smoothing::Exponential { alpha: -0.5 },
+            Smoothing::Exponential { alpha: 1.01 },
+            Smoothing::Exponential { alpha: f64::NAN },
+            // needs the plays, not just the bins
+            Smoothing::Kde { bandwidth_days: 1. },
+        ];
+        for smoothing in rejected {
+            assert!(
+                smooth_series(&series, smoothing).is_err(),
+                "{:?}",
+                smoothing
+            );
+        }
+
+        let points = vec![DataPoint::new("a", 1., 0)];
+        let mut binned = bin_points(&points, TimeStep::Day, Zone::Utc).unwrap();
+        for bandwidth_days in [0., -1., f64::NAN] {
+            let smoothing = Smoothing::Kde { bandwidth_days };
+            assert!(smooth_binned(&mut binned, &points, smoothing).is_err());
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::records::{GroupBy, ListenRecord};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListenSpan {
+    pub first_listen: i64,
+    pub last_listen: i64,
+    // first play longer than the minimum, None if every play was shorter
+    pub first_long_listen: Option<i64>,
+}
+
+impl ListenSpan {
+    // what "discovered in" means, a few seconds of a track on autoplay doesn't count
+    //   unless that's all there is
+    pub fn discovered(&self) -> i64 {
+        self.first_long_listen.unwrap_or(self.first_listen)
+    }
+}
+
+// first and last listen of every key, records can come in any order
+#[derive(Debug, Clone, Default)]
+pub struct ListenSpans {
+    min_ms: u64,
+    spans: HashMap<String, ListenSpan>,
+}
+
+impl ListenSpans {
+    pub fn new(min_ms: u64) -> Self {
+        Self {
+            min_ms,
+            spans: HashMap::new(),
+        }
+    }
+
+    pub fn from_records(records: &[ListenRecord], group_by: GroupBy, min_ms: u64) -> Self {
+        let mut spans = Self::new(min_ms);
+        for record in records {
+            if let Some(key) = group_by.key(record) {
+                spans.add(key, record);
+            }
+        }
+        spans
+    }
+
+    pub fn add(&mut self, key: String, record: &ListenRecord) {
+        let ts = record.timestamp;
+        let is_long = record.ms_played > self.min_ms;
+        let span = self.spans.entry(key).or_insert(ListenSpan {
+            first_listen: ts,
+            last_listen: ts,
+            first_long_listen: None,
+        });
+        span.first_listen = span.first_listen.min(ts);
+        span.last_listen = span.last_listen.max(ts);
+        if is_long {
+            span.first_long_listen = Some(span.first_long_listen.map_or(ts, |first| first.min(ts)));
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ListenSpan> {
+        self.spans.get(key)
+    }
+
+    pub fn spans(&self) -> &HashMap<String, ListenSpan> {
+        &self.spans
+    }
+}
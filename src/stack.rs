@@ -0,0 +1,564 @@
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+// ports of the d3-shape stack offsets
+// https://github.com/d3/d3-shape#stack-offsets
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StackOffset {
+    // zero baseline
+    None,
+    // normalized so every bin sums to 1
+    Expand,
+    // centered around zero
+    Silhouette,
+    // minimizes weighted change in slope, Byron & Wattenberg's streamgraph layout
+    Wiggle,
+    // positive values stacked above zero, negative values below
+    Diverging,
+}
+
+// ports of the d3-shape stack orders, plus orders that need the keys themselves
+// https://github.com/d3/d3-shape#stack-orders
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StackOrder {
+    // order the series were given in
+    None,
+    Reverse,
+    // by total, smallest at the bottom
+    Ascending,
+    Descending,
+    // by the bin each series peaks in, earliest at the bottom
+    Appearance,
+    // earliest peaks in the middle, later peaks alternating above and below, what d3 streamgraphs use
+    InsideOut,
+    // by each key's first listen, earliest at the bottom
+    FirstListen,
+    // keys listed bottom to top, unlisted keys go on top in their original order
+    Explicit(Vec<String>),
+}
+
+// bottom and top of one series at every bin
+#[derive(Debug, Clone, Default)]
+pub struct StackLayer {
+    pub y0: Vec<f64>,
+    pub y1: Vec<f64>,
+}
+
+// `values[key][bin]` are stacked bottom to top in `order` (indices into `values`),
+//   the output layers are indexed the same way as `values`
+pub fn stack(values: &[Vec<f64>], order: &[usize], offset: StackOffset) -> Vec<StackLayer> {
+    assert!(order.len() == values.len());
+    let mut layers: Vec<StackLayer> = values
+        .iter()
+        .map(|series| StackLayer {
+            y0: vec![0.; series.len()],
+            y1: series.iter().map(|&v| finite_or_zero(v)).collect(),
+        })
+        .collect();
+
+    match offset {
+        StackOffset::None => offset_none(&mut layers, order),
+        StackOffset::Expand => offset_expand(&mut layers, order),
+        StackOffset::Silhouette => offset_silhouette(&mut layers, order),
+        StackOffset::Wiggle => offset_wiggle(&mut layers, order),
+        StackOffset::Diverging => offset_diverging(&mut layers, order),
+    }
+    layers
+}
+
+// indices into `values` (and `keys`, `first_listens`) from the bottom of the stack to the top
+// `first_listens` are timestamps, only used for StackOrder::FirstListen
+pub fn stack_order(
+    values: &[Vec<f64>],
+    keys: &[String],
+    first_listens: &[i64],
+    order: &StackOrder,
+) -> Vec<usize> {
+    let identity: Vec<usize> = (0..values.len()).collect();
+    match order {
+        StackOrder::None => identity,
+        StackOrder::Reverse => identity.into_iter().rev().collect(),
+        StackOrder::Ascending => order_ascending(values),
+        StackOrder::Descending => order_ascending(values).into_iter().rev().collect(),
+        StackOrder::Appearance => order_appearance(values),
+        StackOrder::InsideOut => order_inside_out(values),
+        // stable, so keys first heard at the same time keep their original order
+        StackOrder::FirstListen => {
+            let mut order = identity;
+            order.sort_by_key(|&idx| first_listens[idx]);
+            order
+        }
+        StackOrder::Explicit(explicit) => {
+            let mut listed: Vec<usize> = Vec::with_capacity(values.len());
+            for key in explicit {
+                if let Some(idx) = keys.iter().position(|k| k == key) {
+                    if !listed.contains(&idx) {
+                        listed.push(idx);
+                    }
+                }
+            }
+            let unlisted: Vec<usize> = identity
+                .into_iter()
+                .filter(|idx| !listed.contains(idx))
+                .collect();
+            listed.into_iter().chain(unlisted).collect()
+        }
+    }
+}
+
+fn order_ascending(values: &[Vec<f64>]) -> Vec<usize> {
+    let sums: Vec<f64> = values.iter().map(|series| sum(series)).collect();
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| sums[a].total_cmp(&sums[b]));
+    order
+}
+
+fn order_appearance(values: &[Vec<f64>]) -> Vec<usize> {
+    sorted_by_key(values, peak)
+}
+
+fn order_inside_out(values: &[Vec<f64>]) -> Vec<usize> {
+    let sums: Vec<f64> = values.iter().map(|series| sum(series)).collect();
+    let (mut top, mut bottom) = (0., 0.);
+    let (mut tops, mut bottoms): (Vec<usize>, Vec<usize>) = (Vec::new(), Vec::new());
+    for idx in order_appearance(values) {
+        if top < bottom {
+            top += sums[idx];
+            tops.push(idx);
+        } else {
+            bottom += sums[idx];
+            bottoms.push(idx);
+        }
+    }
+    bottoms.into_iter().rev().chain(tops).collect()
+}
+
+// stable, so ties keep their original order
+fn sorted_by_key(values: &[Vec<f64>], key_fn: impl Fn(&[f64]) -> usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by_key(|&idx| key_fn(&values[idx]));
+    order
+}
+
+fn sum(series: &[f64]) -> f64 {
+    series.iter().map(|&v| finite_or_zero(v)).sum()
+}
+
+// index of the first maximum
+fn peak(series: &[f64]) -> usize {
+    series
+        .iter()
+        .enumerate()
+        .fold((0, f64::NEG_INFINITY), |(max_idx, max), (idx, &v)| {
+            if v > max {
+                (idx, v)
+            } else {
+                (max_idx, max)
+            }
+        })
+        .0
+}
+
+// lowest y0 and highest y1 across every layer
+pub fn stack_extent(layers: &[StackLayer]) -> Option<(f64, f64)> {
+    let lowest = layers
+        .iter()
+        .flat_map(|l| l.y0.iter())
+        .copied()
+        .reduce(f64::min);
+    let highest = layers
+        .iter()
+        .flat_map(|l| l.y1.iter())
+        .copied()
+        .reduce(f64::max);
+    lowest.zip(highest)
+}
+
+fn n_bins(layers: &[StackLayer]) -> usize {
+    layers.first().map_or(0, |l| l.y1.len())
+}
+
+// each layer starts where the previous one ends, the first layer's baseline is left as is
+fn offset_none(layers: &mut [StackLayer], order: &[usize]) {
+    for pair in order.windows(2) {
+        let (below, above) = (pair[0], pair[1]);
+        for bin in 0..n_bins(layers) {
+            let base = layers[below].y1[bin];
+            let above = &mut layers[above];
+            above.y0[bin] = base;
+            above.y1[bin] += base;
+        }
+    }
+}
+
+fn offset_expand(layers: &mut [StackLayer], order: &[usize]) {
+    for bin in 0..n_bins(layers) {
+        let total: f64 = layers.iter().map(|l| l.y1[bin]).sum();
+        if total != 0. {
+            for layer in layers.iter_mut() {
+                layer.y1[bin] /= total;
+            }
+        }
+    }
+    offset_none(layers, order);
+}
+
+fn offset_silhouette(layers: &mut [StackLayer], order: &[usize]) {
+    let first = match order.first() {
+        Some(&first) => first,
+        None => return,
+    };
+    for bin in 0..n_bins(layers) {
+        let total: f64 = layers.iter().map(|l| l.y1[bin]).sum();
+        layers[first].y0[bin] = -total / 2.;
+        layers[first].y1[bin] -= total / 2.;
+    }
+    offset_none(layers, order);
+}
+
+fn offset_wiggle(layers: &mut [StackLayer], order: &[usize]) {
+    let first = match order.first() {
+        Some(&first) => first,
+        None => return,
+    };
+    let n = n_bins(layers);
+    if n == 0 {
+        return;
+    }
+
+    let mut baseline = 0.;
+    let mut baselines = vec![0.; n];
+    for bin in 1..n {
+        let (mut total, mut weighted_slope) = (0., 0.);
+        // change in the top of every layer below the current one
+        let mut below_delta = 0.;
+        for &key in order {
+            let cur = layers[key].y1[bin];
+            let prev = layers[key].y1[bin - 1];
+            let slope = (cur - prev) / 2. + below_delta;
+            below_delta += cur - prev;
+            total += cur;
+            weighted_slope += slope * cur;
+        }
+        baselines[bin - 1] = baseline;
+        if total != 0. {
+            baseline -= weighted_slope / total;
+        }
+    }
+    baselines[n - 1] = baseline;
+
+    for (bin, baseline) in baselines.into_iter().enumerate() {
+        layers[first].y0[bin] = baseline;
+        layers[first].y1[bin] += baseline;
+    }
+    offset_none(layers, order);
+}
+
+fn offset_diverging(layers: &mut [StackLayer], order: &[usize]) {
+    for bin in 0..n_bins(layers) {
+        let (mut positive, mut negative) = (0., 0.);
+        for &key in order {
+            let layer = &mut layers[key];
+            let dy = layer.y1[bin] - layer.y0[bin];
+            if dy > 0. {
+                layer.y0[bin] = positive;
+                positive += dy;
+                layer.y1[bin] = positive;
+            } else if dy < 0. {
+                layer.y1[bin] = negative;
+                negative += dy;
+                layer.y0[bin] = negative;
+            } else {
+                layer.y0[bin] = 0.;
+                layer.y1[bin] = dy;
+            }
+        }
+    }
+}
+
+fn finite_or_zero(val: f64) -> f64 {
+    if val.is_finite() {
+        val
+    } else {
+        0.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // expected layouts below were worked out with d3-shape 3.2's stack offsets and orders
+    fn values() -> Vec<Vec<f64>> {
+        vec![
+            vec![1., 4., 2., 0., 3., 1.],
+            vec![5., 1., 0., 2., 2., 6.],
+            vec![0., 2., 7., 3., 1., 0.],
+            vec![2., 2., 2., 2., 2., 2.],
+            vec![3., 0., 1., 6., 0., 1.],
+        ]
+    }
+
+    fn keys(n: usize) -> Vec<String> {
+        (0..n).map(|idx| format!("key {}", idx)).collect()
+    }
+
+    fn order(order: StackOrder) -> Vec<usize> {
+        let values = values();
+        stack_order(&values, &keys(values.len()), &[0; 5], &order)
+    }
+
+    fn assert_layers(layers: &[StackLayer], expected: &[[&[f64]; 2]]) {
+        assert_eq!(layers.len(), expected.len());
+        for (layer, [y0, y1]) in layers.iter().zip(expected) {
+            for (actual, expected) in [(&layer.y0, y0), (&layer.y1, y1)] {
+                assert_eq!(actual.len(), expected.len());
+                for (a, e) in actual.iter().zip(expected.iter()) {
+                    assert!((a - e).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn orders_match_d3() {
+        assert_eq!(order(StackOrder::None), [0, 1, 2, 3, 4]);
+        assert_eq!(order(StackOrder::Reverse), [4, 3, 2, 1, 0]);
+        // keys 0 and 4 both sum to 11 and keep their order
+        assert_eq!(order(StackOrder::Ascending), [0, 4, 3, 2, 1]);
+        assert_eq!(order(StackOrder::Descending), [1, 2, 3, 4, 0]);
+        // key 3 is flat, its first bin counts as its peak
+        assert_eq!(order(StackOrder::Appearance), [3, 0, 2, 4, 1]);
+        assert_eq!(order(StackOrder::InsideOut), [1, 4, 3, 0, 2]);
+    }
+
+    #[test]
+    fn first_listen_orders_by_timestamp_and_keeps_ties_in_order() {
+        let values = vec![vec![1.]; 4];
+        let first_listens = [300, 100, 300, 50];
+        let order = stack_order(&values, &keys(4), &first_listens, &StackOrder::FirstListen);
+        assert_eq!(order, [3, 1, 0, 2]);
+    }
+
+    #[test]
+    fn explicit_order_skips_unknown_and_repeated_keys() {
+        let explicit = ["key 3", "nope", "key 1", "key 3"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(order(StackOrder::Explicit(explicit)), [3, 1, 0, 2, 4]);
+        assert_eq!(order(StackOrder::Explicit(Vec::new())), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn offset_none_matches_d3() {
+        let layers = stack(&values(), &order(StackOrder::InsideOut), StackOffset::None);
+        assert_layers(
+            &layers,
+            &[
+                [&[10., 3., 3., 10., 4., 9.], &[11., 7., 5., 10., 7., 10.]],
+                [&[0., 0., 0., 0., 0., 0.], &[5., 1., 0., 2., 2., 6.]],
+                [&[11., 7., 5., 10., 7., 10.], &[11., 9., 12., 13., 8., 10.]],
+                [&[8., 1., 1., 8., 2., 7.], &[10., 3., 3., 10., 4., 9.]],
+                [&[5., 1., 0., 2., 2., 6.], &[8., 1., 1., 8., 2., 7.]],
+            ],
+        );
+    }
+
+    #[test]
+    fn offset_expand_matches_d3() {
+        let layers = stack(
+            &values(),
+            &order(StackOrder::InsideOut),
+            StackOffset::Expand,
+        );
+        assert_layers(
+            &layers,
+            &[
+                [
+                    &[10. / 11., 3. / 9., 3. / 12., 10. / 13., 4. / 8., 9. / 10.],
+                    &[1., 7. / 9., 5. / 12., 10. / 13., 7. / 8., 1.],
+                ],
+                [
+                    &[0., 0., 0., 0., 0., 0.],
+                    &[5. / 11., 1. / 9., 0., 2. / 13., 2. / 8., 6. / 10.],
+                ],
+                [
+                    &[1., 7. / 9., 5. / 12., 10. / 13., 7. / 8., 1.],
+                    &[1., 1., 1., 1., 1., 1.],
+                ],
+                [
+                    &[8. / 11., 1. / 9., 1. / 12., 8. / 13., 2. / 8., 7. / 10.],
+                    &[10. / 11., 3. / 9., 3. / 12., 10. / 13., 4. / 8., 9. / 10.],
+                ],
+                [
+                    &[5. / 11., 1. / 9., 0., 2. / 13., 2. / 8., 6. / 10.],
+                    &[8. / 11., 1. / 9., 1. / 12., 8. / 13., 2. / 8., 7. / 10.],
+                ],
+            ],
+        );
+    }
+
+    #[test]
+    fn offset_silhouette_matches_d3() {
+        let layers = stack(
+            &values(),
+            &order(StackOrder::InsideOut),
+            StackOffset::Silhouette,
+        );
+        assert_layers(
+            &layers,
+            &[
+                [
+                    &[4.5, -1.5, -3., 3.5, 0., 4.],
+                    &[5.5, 2.5, -1., 3.5, 3., 5.],
+                ],
+                [
+                    &[-5.5, -4.5, -6., -6.5, -4., -5.],
+                    &[-0.5, -3.5, -6., -4.5, -2., 1.],
+                ],
+                [&[5.5, 2.5, -1., 3.5, 3., 5.], &[5.5, 4.5, 6., 6.5, 4., 5.]],
+                [
+                    &[2.5, -3.5, -5., 1.5, -2., 2.],
+                    &[4.5, -1.5, -3., 3.5, 0., 4.],
+                ],
+                [
+                    &[-0.5, -3.5, -6., -4.5, -2., 1.],
+                    &[2.5, -3.5, -5., 1.5, -2., 2.],
+                ],
+            ],
+        );
+    }
+
+    #[test]
+    fn offset_wiggle_matches_d3() {
+        let layers = stack(
+            &values(),
+            &order(StackOrder::InsideOut),
+            StackOffset::Wiggle,
+        );
+        assert_layers(
+            &layers,
+            &[
+                [
+                    &[
+                        10.,
+                        7.888888888888889,
+                        7.805555555555556,
+                        10.805555555555557,
+                        8.493055555555557,
+                        10.443055555555556,
+                    ],
+                    &[
+                        11.,
+                        11.88888888888889,
+                        9.805555555555557,
+                        10.805555555555557,
+                        11.493055555555557,
+                        11.443055555555556,
+                    ],
+                ],
+                [
+                    &[
+                        0.,
+                        4.888888888888889,
+                        4.805555555555556,
+                        0.8055555555555562,
+                        4.493055555555556,
+                        1.4430555555555564,
+                    ],
+                    &[
+                        5.,
+                        5.888888888888889,
+                        4.805555555555556,
+                        2.8055555555555562,
+                        6.493055555555556,
+                        7.443055555555556,
+                    ],
+                ],
+                [
+                    &[
+                        11.,
+                        11.88888888888889,
+                        9.805555555555557,
+                        10.805555555555557,
+                        11.493055555555557,
+                        11.443055555555556,
+                    ],
+                    &[
+                        11.,
+                        13.88888888888889,
+                        16.805555555555557,
+                        13.805555555555557,
+                        12.493055555555557,
+                        11.443055555555556,
+                    ],
+                ],
+                [
+                    &[
+                        8.,
+                        5.888888888888889,
+                        5.805555555555556,
+                        8.805555555555557,
+                        6.493055555555556,
+                        8.443055555555556,
+                    ],
+                    &[
+                        10.,
+                        7.888888888888889,
+                        7.805555555555556,
+                        10.805555555555557,
+                        8.493055555555557,
+                        10.443055555555556,
+                    ],
+                ],
+                [
+                    &[
+                        5.,
+                        5.888888888888889,
+                        4.805555555555556,
+                        2.8055555555555562,
+                        6.493055555555556,
+                        7.443055555555556,
+                    ],
+                    &[
+                        8.,
+                        5.888888888888889,
+                        5.805555555555556,
+                        8.805555555555557,
+                        6.493055555555556,
+                        8.443055555555556,
+                    ],
+                ],
+            ],
+        );
+    }
+
+    #[test]
+    fn offset_diverging_matches_d3() {
+        let values = vec![
+            vec![1., -2., 3., 0.],
+            vec![-1., 4., -3., 2.],
+            vec![2., 0., -1., -2.],
+        ];
+        let layers = stack(&values, &[0, 1, 2], StackOffset::Diverging);
+        assert_layers(
+            &layers,
+            &[
+                [&[0., -2., 0., 0.], &[1., 0., 3., 0.]],
+                [&[-1., 0., -3., 0.], &[0., 4., 0., 2.]],
+                [&[1., 0., -4., -2.], &[3., 0., -3., 0.]],
+            ],
+        );
+    }
+
+    #[test]
+    fn non_finite_values_count_as_zero() {
+        let values = vec![vec![f64::NAN, 1.], vec![2., f64::INFINITY]];
+        let layers = stack(&values, &[0, 1], StackOffset::None);
+        assert_layers(&layers, &[[&[0., 0.], &[0., 1.]], [&[0., 1.], &[2., 1.]]]);
+        assert_eq!(stack_extent(&layers), Some((0., 2.)));
+    }
+}
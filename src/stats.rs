@@ -0,0 +1,565 @@
+use crate::binning::{bin_edges, TimeStep};
+use crate::filter::RecordFilter;
+use crate::normalize::{ArtistNormalization, ArtistNormalizer};
+use crate::records::{ContentKind, GroupBy, ListenRecord};
+use crate::zone::Zone;
+use chrono::{Datelike, NaiveDate, Timelike};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StatsConfig {
+    // what top lists, streaks and per-key listens are about
+    pub group_by: GroupBy,
+    pub artists: ArtistNormalization,
+    pub filter: RecordFilter,
+    // length of the periods in top_per_period
+    pub period: TimeStep,
+    // length of the top lists, and of the streak list
+    pub top_n: usize,
+    // days, hours and periods follow the wall clock here
+    pub time_zone: Zone,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            group_by: GroupBy::Artist,
+            artists: ArtistNormalization::default(),
+            filter: RecordFilter::default(),
+            period: TimeStep::Year,
+            top_n: 10,
+            time_zone: Zone::Utc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Totals {
+    pub plays: usize,
+    pub ms_played: u64,
+    // days with at least one play
+    pub days: usize,
+    pub first_play: Option<i64>,
+    pub last_play: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DistinctCounts {
+    pub keys: usize,
+    pub artists: usize,
+    pub albums: usize,
+    // track and artist, same as GroupBy::Track
+    pub tracks: usize,
+    pub shows: usize,
+    pub episodes: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyTotal {
+    pub key: String,
+    pub plays: usize,
+    pub ms_played: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeriodTop {
+    // ms since epoch, end is exclusive
+    pub start: i64,
+    pub end: i64,
+    // by ms played
+    pub top: Vec<KeyTotal>,
+}
+
+// consecutive days with at least one play of a key, dates are YYYY-MM-DD
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Streak {
+    pub key: String,
+    pub days: usize,
+    pub first_day: String,
+    pub last_day: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DayTotal {
+    pub day: String,
+    pub plays: usize,
+    pub ms_played: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyListens {
+    pub key: String,
+    pub plays: usize,
+    pub ms_played: u64,
+    pub first_listen: i64,
+    pub last_listen: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListeningStats {
+    pub totals: Totals,
+    pub distinct: DistinctCounts,
+    pub top_keys: Vec<KeyTotal>,
+    pub top_per_period: Vec<PeriodTop>,
+    // each key's longest streak, longest first
+    pub streaks: Vec<Streak>,
+    // ms played in every hour of the day (0 is midnight) and every weekday (0 is monday)
+    pub ms_by_hour: [u64; 24],
+    pub ms_by_weekday: [u64; 7],
+    pub busiest_hour: Option<usize>,
+    pub busiest_weekday: Option<usize>,
+    pub busiest_day: Option<DayTotal>,
+    // every key in order of first listen
+    pub keys: Vec<KeyListens>,
+}
+
+pub fn listening_stats(
+    records: &[ListenRecord],
+    config: &StatsConfig,
+) -> Result<ListeningStats, String> {
+    let zone = config.time_zone;
+    let records = ArtistNormalizer::new(&config.artists).normalize_all(records);
+    // records without a key still count towards the totals
+    let (mut records, _) = config.filter.apply(&records, config.group_by);
+    records.sort_by_key(|record| record.timestamp);
+
+    let mut totals = Totals {
+        first_play: records.first().map(|r| r.timestamp),
+        last_play: records.last().map(|r| r.timestamp),
+        ..Default::default()
+    };
+    let mut artists: HashSet<&str> = HashSet::new();
+    let mut albums: HashSet<(&str, &str)> = HashSet::new();
+    let mut tracks: HashSet<(&str, &str)> = HashSet::new();
+    let mut shows: HashSet<&str> = HashSet::new();
+    let mut episodes: HashSet<(&str, &str)> = HashSet::new();
+    let mut ms_by_hour = [0; 24];
+    let mut ms_by_weekday = [0; 7];
+    let mut by_day: HashMap<NaiveDate, DayTotal> = HashMap::new();
+
+    let mut keys: Vec<KeyListens> = Vec::new();
+    let mut key_indices: HashMap<String, usize> = HashMap::new();
+    let mut key_days: Vec<BTreeSet<NaiveDate>> = Vec::new();
+    // every record that has a key, for the per-period lists
+    let mut keyed: Vec<(usize, &ListenRecord)> = Vec::new();
+
+    for &record in &records {
+        let local = zone.to_local(record.timestamp)?;
+        let day = local.date();
+        totals.plays += 1;
+        totals.ms_played += record.ms_played;
+        ms_by_hour[local.hour() as usize] += record.ms_played;
+        ms_by_weekday[day.weekday().num_days_from_monday() as usize] += record.ms_played;
+        let day_total = by_day.entry(day).or_insert_with(|| DayTotal {
+            day: day.to_string(),
+            plays: 0,
+            ms_played: 0,
+        });
+        day_total.plays += 1;
+        day_total.ms_played += record.ms_played;
+
+        match record.kind {
+            ContentKind::Music => {
+                artists.insert(&record.artist_name);
+                tracks.insert((&record.track_name, &record.artist_name));
+                if let Some(album) = &record.album_name {
+                    albums.insert((album, &record.artist_name));
+                }
+            }
+            ContentKind::Podcast => {
+                shows.insert(&record.artist_name);
+                episodes.insert((&record.track_name, &record.artist_name));
+            }
+        }
+
+        let key = match config.group_by.key(record) {
+            Some(key) => key,
+            None => continue,
+        };
+        let key_idx = match key_indices.get(&key) {
+            Some(&idx) => idx,
+            None => {
+                key_indices.insert(key.clone(), keys.len());
+                keys.push(KeyListens {
+                    key,
+                    plays: 0,
+                    ms_played: 0,
+                    first_listen: record.timestamp,
+                    last_listen: record.timestamp,
+                });
+                key_days.push(BTreeSet::new());
+                keys.len() - 1
+            }
+        };
+        let listens = &mut keys[key_idx];
+        listens.plays += 1;
+        listens.ms_played += record.ms_played;
+        // records are sorted, so the first one seen is the first listen
+        listens.last_listen = record.timestamp;
+        key_days[key_idx].insert(day);
+        keyed.push((key_idx, record));
+    }
+    totals.days = by_day.len();
+
+    let key_total = |idx: usize, plays: usize, ms_played: u64| KeyTotal {
+        key: keys[idx].key.clone(),
+        plays,
+        ms_played,
+    };
+    let all_time: Vec<(usize, usize, u64)> = keys
+        .iter()
+        .enumerate()
+        .map(|(idx, listens)| (idx, listens.plays, listens.ms_played))
+        .collect();
+    let top_keys = top_n(all_time, config.top_n)
+        .into_iter()
+        .map(|(idx, plays, ms)| key_total(idx, plays, ms))
+        .collect();
+
+    let mut top_per_period: Vec<PeriodTop> = Vec::new();
+    if let (Some(first), Some(last)) = (totals.first_play, totals.last_play) {
+        let edges = bin_edges(first, last, config.period, zone)?;
+        let mut rest = keyed.as_slice();
+        for period in edges.windows(2) {
+            let (start, end) = (period[0], period[1]);
+            let in_period = rest.partition_point(|(_, record)| record.timestamp < end);
+            let mut sums: HashMap<usize, (usize, u64)> = HashMap::new();
+            for (key_idx, record) in &rest[..in_period] {
+                let sum = sums.entry(*key_idx).or_insert((0, 0));
+                sum.0 += 1;
+                sum.1 += record.ms_played;
+            }
+            rest = &rest[in_period..];
+            let sums: Vec<(usize, usize, u64)> = sums
+                .into_iter()
+                .map(|(idx, (plays, ms))| (idx, plays, ms))
+                .collect();
+            top_per_period.push(PeriodTop {
+                start,
+                end,
+                top: top_n(sums, config.top_n)
+                    .into_iter()
+                    .map(|(idx, plays, ms)| key_total(idx, plays, ms))
+                    .collect(),
+            });
+        }
+    }
+
+    let mut streaks: Vec<Streak> = key_days
+        .iter()
+        .enumerate()
+        .filter_map(|(key_idx, days)| {
+            let (first_day, last_day, days) = longest_streak(days)?;
+            Some(Streak {
+                key: keys[key_idx].key.clone(),
+                days,
+                first_day: first_day.to_string(),
+                last_day: last_day.to_string(),
+            })
+        })
+        .collect();
+    // stable, so ties stay in order of first listen
+    streaks.sort_by_key(|streak| Reverse(streak.days));
+    streaks.truncate(config.top_n);
+
+    let busiest = |ms: &[u64]| {
+        (0..ms.len())
+            .filter(|&idx| ms[idx] > 0)
+            .max_by_key(|&idx| (ms[idx], Reverse(idx)))
+    };
+    let busiest_day = by_day
+        .into_values()
+        .max_by(|a, b| a.ms_played.cmp(&b.ms_played).then(b.day.cmp(&a.day)));
+
+    Ok(ListeningStats {
+        distinct: DistinctCounts {
+            keys: keys.len(),
+            artists: artists.len(),
+            albums: albums.len(),
+            tracks: tracks.len(),
+            shows: shows.len(),
+            episodes: episodes.len(),
+        },
+        totals,
+        top_keys,
+        top_per_period,
+        streaks,
+        busiest_hour: busiest(&ms_by_hour),
+        busiest_weekday: busiest(&ms_by_weekday),
+        ms_by_hour,
+        ms_by_weekday,
+        busiest_day,
+        keys,
+    })
+}
+
+// (idx, plays, ms played) by ms played then plays, ties go to the lower index
+fn top_n(mut totals: Vec<(usize, usize, u64)>, n: usize) -> Vec<(usize, usize, u64)> {
+    totals.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.cmp(&a.1)).then(a.0.cmp(&b.0)));
+    totals.truncate(n);
+    totals
+}
+
+// first day, last day and length of the longest run of consecutive days, the earliest if tied
+fn longest_streak(days: &BTreeSet<NaiveDate>) -> Option<(NaiveDate, NaiveDate, usize)> {
+    let mut best: Option<(NaiveDate, NaiveDate, usize)> = None;
+    let mut run: Option<(NaiveDate, NaiveDate, usize)> = None;
+    for &day in days {
+        let (first, len) = match run {
+            Some((first, last, len)) if last.succ_opt() == Some(day) => (first, len + 1),
+            _ => (day, 1),
+        };
+        run = Some((first, day, len));
+        if best.is_none_or(|(_, _, best_len)| len > best_len) {
+            best = run;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: u64 = 60 * 1000;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32) -> i64 {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp_millis()
+    }
+
+    fn play(artist: &str, timestamp: i64, ms_played: u64) -> ListenRecord {
+        ListenRecord {
+            timestamp,
+            ms_played,
+            track_name: "track".into(),
+            artist_name: artist.into(),
+            ..Default::default()
+        }
+    }
+
+    fn stats(records: &[ListenRecord], config: StatsConfig) -> ListeningStats {
+        listening_stats(records, &config).unwrap()
+    }
+
+    fn streak(streak: &Streak) -> (&str, usize, &str, &str) {
+        (
+            &streak.key,
+            streak.days,
+            &streak.first_day,
+            &streak.last_day,
+        )
+    }
+
+    fn top(top: &[KeyTotal]) -> Vec<(&str, usize, u64)> {
+        top.iter()
+            .map(|total| (total.key.as_str(), total.plays, total.ms_played))
+            .collect()
+    }
+
+    #[test]
+    fn streaks_run_over_month_ends_and_stop_at_gaps() {
+        let records = vec![
+            // a gap after four days, then two more
+            play("a", utc(2021, 1, 30, 12), MINUTE),
+            play("a", utc(2021, 1, 31, 12), MINUTE),
+            play("a", utc(2021, 2, 1, 12), MINUTE),
+            play("a", utc(2021, 2, 1, 18), MINUTE),
+            play("a", utc(2021, 2, 2, 12), MINUTE),
+            play("a", utc(2021, 2, 4, 12), MINUTE),
+            play("a", utc(2021, 2, 5, 12), MINUTE),
+            // two runs of three, the earlier one counts
+            play("b", utc(2021, 3, 1, 12), MINUTE),
+            play("b", utc(2021, 3, 2, 12), MINUTE),
+            play("b", utc(2021, 3, 3, 12), MINUTE),
+            play("b", utc(2021, 3, 10, 12), MINUTE),
+            play("b", utc(2021, 3, 11, 12), MINUTE),
+            play("b", utc(2021, 3, 12, 12), MINUTE),
+            // a single day of plays
+            play("c", utc(2021, 4, 1, 10), MINUTE),
+            play("c", utc(2021, 4, 1, 11), MINUTE),
+            // as long as b's, but first heard later
+            play("d", utc(2021, 12, 30, 12), MINUTE),
+            play("d", utc(2021, 12, 31, 12), MINUTE),
+            play("d", utc(2022, 1, 1, 12), MINUTE),
+        ];
+        let result = stats(&records, StatsConfig::default());
+        let streaks: Vec<_> = result.streaks.iter().map(streak).collect();
+        assert_eq!(
+            streaks,
+            [
+                ("a", 4, "2021-01-30", "2021-02-02"),
+                ("b", 3, "2021-03-01", "2021-03-03"),
+                ("d", 3, "2021-12-30", "2022-01-01"),
+                ("c", 1, "2021-04-01", "2021-04-01"),
+            ]
+        );
+
+        let config = StatsConfig {
+            top_n: 2,
+            ..Default::default()
+        };
+        let result = stats(&records, config);
+        let keys: Vec<&str> = result.streaks.iter().map(|s| s.key.as_str()).collect();
+        assert_eq!(keys, ["a", "b"]);
+    }
+
+    #[test]
+    fn streak_days_follow_the_time_zone() {
+        // 23:30 and 00:30 utc are the same evening in new york
+        let records = vec![
+            play("a", utc(2021, 1, 4, 23) + 30 * MINUTE as i64, MINUTE),
+            play("a", utc(2021, 1, 5, 0) + 30 * MINUTE as i64, MINUTE),
+        ];
+        let result = stats(&records, StatsConfig::default());
+        assert_eq!(result.streaks[0].days, 2);
+        let config = StatsConfig {
+            time_zone: Zone::parse("America/New_York").unwrap(),
+            ..Default::default()
+        };
+        let result = stats(&records, config);
+        assert_eq!(
+            streak(&result.streaks[0]),
+            ("a", 1, "2021-01-04", "2021-01-04")
+        );
+    }
+
+    #[test]
+    fn top_keys_per_period() {
+        let records = vec![
+            play("a", utc(2021, 1, 5, 12), 3 * MINUTE),
+            play("b", utc(2021, 1, 6, 12), 5 * MINUTE),
+            play("c", utc(2021, 1, 31, 23), MINUTE),
+            // nothing in february
+            play("c", utc(2021, 3, 2, 12), 10 * MINUTE),
+            // same time as c, but more plays
+            play("a", utc(2021, 3, 3, 12), 5 * MINUTE),
+            play("a", utc(2021, 3, 4, 12), 5 * MINUTE),
+            play("b", utc(2021, 3, 31, 23), MINUTE),
+        ];
+        let config = StatsConfig {
+            period: TimeStep::Month,
+            top_n: 2,
+            ..Default::default()
+        };
+        let result = stats(&records, config);
+
+        let periods: Vec<_> = result
+            .top_per_period
+            .iter()
+            .map(|period| (period.start, period.end, top(&period.top)))
+            .collect();
+        assert_eq!(
+            periods,
+            [
+                (
+                    utc(2021, 1, 1, 0),
+                    utc(2021, 2, 1, 0),
+                    vec![("b", 1, 5 * MINUTE), ("a", 1, 3 * MINUTE)]
+                ),
+                (utc(2021, 2, 1, 0), utc(2021, 3, 1, 0), vec![]),
+                (
+                    utc(2021, 3, 1, 0),
+                    utc(2021, 4, 1, 0),
+                    vec![("a", 2, 10 * MINUTE), ("c", 1, 10 * MINUTE)]
+                ),
+            ]
+        );
+        assert_eq!(
+            top(&result.top_keys),
+            [("a", 3, 13 * MINUTE), ("c", 2, 11 * MINUTE)]
+        );
+    }
+
+    #[test]
+    fn busiest_hour_and_weekday_are_local() {
+        // monday 03:00 utc is sunday 22:00 in new york, monday 15:00 utc is 10:00 there
+        let records = vec![
+            play("a", utc(2021, 1, 4, 3), 30 * MINUTE),
+            play("a", utc(2021, 1, 4, 15), 10 * MINUTE),
+        ];
+        let result = stats(&records, StatsConfig::default());
+        assert_eq!(result.busiest_hour, Some(3));
+        assert_eq!(result.busiest_weekday, Some(0));
+        assert_eq!(result.totals.days, 1);
+
+        let config = StatsConfig {
+            time_zone: Zone::parse("America/New_York").unwrap(),
+            ..Default::default()
+        };
+        let result = stats(&records, config);
+        assert_eq!(result.busiest_hour, Some(22));
+        assert_eq!(result.busiest_weekday, Some(6));
+        assert_eq!(result.ms_by_hour[22], 30 * MINUTE);
+        assert_eq!(result.ms_by_hour[10], 10 * MINUTE);
+        assert_eq!(result.ms_by_weekday[0], 10 * MINUTE);
+        assert_eq!(result.totals.days, 2);
+        assert_eq!(result.busiest_day.unwrap().day, "2021-01-03");
+    }
+
+    #[test]
+    fn nothing_played_has_no_busiest_hour() {
+        let result = stats(&[], StatsConfig::default());
+        assert_eq!(result.busiest_hour, None);
+        assert_eq!(result.busiest_weekday, None);
+        assert!(result.busiest_day.is_none());
+        assert!(result.top_per_period.is_empty());
+    }
+
+    #[test]
+    fn distinct_counts() {
+        let track = |track: &str, artist: &str, album: Option<&str>| ListenRecord {
+            track_name: track.into(),
+            album_name: album.map(String::from),
+            ..play(artist, utc(2021, 1, 1, 12), MINUTE)
+        };
+        let episode = |episode: &str, show: &str| ListenRecord {
+            kind: ContentKind::Podcast,
+            ..track(episode, show, None)
+        };
+        let records = vec![
+            track("one", "a", Some("x")),
+            track("one", "a", Some("x")),
+            // same name, other artist
+            track("one", "b", Some("y")),
+            track("two", "a", Some("x")),
+            // same album name, other artist
+            track("three", "b", Some("x")),
+            track("four", "a", None),
+            episode("first", "show"),
+            episode("first", "show"),
+            episode("first", "other show"),
+        ];
+        let result = stats(&records, StatsConfig::default());
+        let distinct = result.distinct;
+        // podcasts have no artist to group by, but still count towards the totals
+        assert_eq!(distinct.keys, 2);
+        assert_eq!(result.totals.plays, 9);
+        assert_eq!(distinct.artists, 2);
+        assert_eq!(distinct.albums, 3);
+        assert_eq!(distinct.tracks, 5);
+        assert_eq!(distinct.shows, 2);
+        assert_eq!(distinct.episodes, 2);
+
+        let config = StatsConfig {
+            group_by: GroupBy::Track,
+            ..Default::default()
+        };
+        assert_eq!(stats(&records, config).distinct.keys, 5);
+    }
+}
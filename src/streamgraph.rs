@@ -0,0 +1,607 @@
+use crate::binning::{bin_points, BinnedSeries, DataPoint, TimeStep, TopKeys};
+use crate::color::{interpolate_rainbow, key_noise, pick_color, to_hex, Rgb};
+use crate::curve::curve_basis;
+use crate::filter::{FilterReason, FilterReport, RecordFilter};
+use crate::normalize::{ArtistNormalization, ArtistNormalizer};
+use crate::records::{GroupBy, ListenRecord, ScoreMode};
+use crate::sessions::{assign_sessions, SessionRules};
+use crate::smooth::{smooth_binned, Smoothing};
+use crate::spans::ListenSpans;
+use crate::stack::{stack, stack_extent, stack_order, StackLayer, StackOffset, StackOrder};
+use crate::triangulate::{SampleMode, DEFAULT_SAMPLES_PER_SEGMENT};
+use crate::zone::Zone;
+use crate::{Point, TriangulatedArea};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const MINUTE: u64 = 60 * 1000;
+const OTHER_COLOR: Rgb = [0x99, 0x99, 0x99];
+
+// whether it's the other series, and its key, a real key can have the other series' name
+type SeriesId = (bool, String);
+
+// everything needed to go from listening records to a chart, defaults match graph.ts
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StreamgraphConfig {
+    // size of the chart in canvas pixels
+    pub width: f64,
+    pub height: f64,
+    // records without the grouped field are left out
+    pub group_by: GroupBy,
+    // applied before anything else, filters and keys see the normalized names
+    pub artists: ArtistNormalization,
+    pub filter: RecordFilter,
+    pub score: ScoreMode,
+    // only used for ScoreMode::Sessions
+    pub sessions: SessionRules,
+    pub time_step: TimeStep,
+    // bins follow the wall clock here
+    pub time_zone: Zone,
+    // keys that don't make the cut share one "Other" series, None keeps every key
+    pub top_keys: Option<TopKeys>,
+    // applied to the bins before the top keys are picked
+    pub smoothing: Smoothing,
+    pub offset: StackOffset,
+    pub order: StackOrder,
+    pub samples_per_segment: i32,
+    pub sample_mode: SampleMode,
+    // a key's color comes from its first play longer than this
+    pub min_first_listen_ms: u64,
+    // how far each key's color is pushed along the rainbow, keeps neighbouring keys apart
+    pub color_noise: f64,
+}
+
+impl Default for StreamgraphConfig {
+    fn default() -> Self {
+        Self {
+            width: 1000.,
+            height: 333.,
+            group_by: GroupBy::Artist,
+            artists: ArtistNormalization::default(),
+            filter: RecordFilter::default(),
+            score: ScoreMode::MsPlayed,
+            sessions: SessionRules::default(),
+            time_step: TimeStep::Month,
+            time_zone: Zone::Utc,
+            top_keys: None,
+            smoothing: Smoothing::None,
+            offset: StackOffset::Wiggle,
+            order: StackOrder::InsideOut,
+            samples_per_segment: DEFAULT_SAMPLES_PER_SEGMENT,
+            sample_mode: SampleMode::Uniform,
+            min_first_listen_ms: MINUTE,
+            color_noise: 0.04,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyInfo {
+    pub key: String,
+    // css hex colors
+    pub color: String,
+    // unique per key, for finding the key under the cursor on an offscreen canvas
+    pub pick_color: String,
+    // first play longer than min_first_listen_ms, or the first play if none were
+    pub first_listen: i64,
+    pub last_listen: i64,
+    pub total_score: f64,
+    // position in the stack, 0 is the bottom
+    pub stack_index: usize,
+    // the series every key outside the top keys is summed into
+    pub is_other: bool,
+}
+
+pub struct Streamgraph {
+    pub config: StreamgraphConfig,
+    pub binned: BinnedSeries,
+    // indexed like binned.keys
+    pub layers: Vec<StackLayer>,
+    pub areas: Vec<TriangulatedArea>,
+    pub keys: Vec<KeyInfo>,
+    pub filter_report: FilterReport,
+    // canvas x of every bin
+    pub xs: Vec<f64>,
+    // stacked value -> canvas y is y_offset - value * y_scale
+    pub y_scale: f64,
+    pub y_offset: f64,
+}
+
+impl Streamgraph {
+    // bin -> stack -> curve -> triangulate -> color
+    pub fn build(records: &[ListenRecord], config: StreamgraphConfig) -> Result<Self, String> {
+        config.validate()?;
+        let mut spans = ListenSpans::new(config.min_first_listen_ms);
+        let mut artists = ArtistNormalizer::new(&config.artists);
+        let (points, filter_report) = collect_points(records, &config, &mut spans, &mut artists)?;
+        let raw = bin_points(&points, config.time_step, config.time_zone)?;
+        let (graph, _) = Self::layout(config, &raw, &points, &spans, filter_report, None)?;
+        Ok(graph)
+    }
+
+    // a chart with nothing in it
+    pub fn empty(config: StreamgraphConfig) -> Self {
+        Self {
+            binned: BinnedSeries {
+                step: config.time_step,
+                zone: config.time_zone,
+                bin_edges: Vec::new(),
+                keys: Vec::new(),
+                scores: Vec::new(),
+                folded: Vec::new(),
+            },
+            config,
+            layers: Vec::new(),
+            areas: Vec::new(),
+            keys: Vec::new(),
+            filter_report: FilterReport::default(),
+            xs: Vec::new(),
+            y_scale: 1.,
+            y_offset: 0.,
+        }
+    }
+
+    // everything after binning, `raw` is straight from bin_points() and `points` is what it
+    //   was made from
+    // areas of series whose outline is the same as in `previous` are copied over instead of
+    //   being triangulated again, the returned flags mark the series that changed in any way
+    pub(crate) fn layout(
+        config: StreamgraphConfig,
+        raw: &BinnedSeries,
+        points: &[DataPoint],
+        spans: &ListenSpans,
+        filter_report: FilterReport,
+        previous: Option<&Streamgraph>,
+    ) -> Result<(Self, Vec<bool>), String> {
+        let mut binned = raw.clone();
+        smooth_binned(&mut binned, points, config.smoothing)?;
+        if config.score == ScoreMode::ShareOfBin {
+            binned.to_share_of_bin();
+        }
+        if let Some(top_keys) = config.top_keys {
+            binned.fold_others(top_keys);
+        }
+
+        let lowest_ts = points.iter().map(|p| p.timestamp).min().unwrap_or(0);
+        let highest_ts = points.iter().map(|p| p.timestamp).max().unwrap_or(0);
+        let ts_span = (highest_ts - lowest_ts).max(1) as f64;
+        // first and last listen of every series, the Other series spans every key in it
+        let listens: Vec<(i64, i64)> = binned
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(key_idx, key)| {
+                let (first_listen, last_listen) = if binned.other_index() == Some(key_idx) {
+                    let folded = || binned.folded.iter().filter_map(|key| spans.get(key));
+                    let first_listen = folded().map(|span| span.discovered()).min();
+                    let last_listen = folded().map(|span| span.last_listen).max();
+                    (first_listen, last_listen)
+                } else {
+                    let span = spans.get(key);
+                    (
+                        span.map(|span| span.discovered()),
+                        span.map(|span| span.last_listen),
+                    )
+                };
+                (
+                    first_listen.unwrap_or(lowest_ts),
+                    last_listen.unwrap_or(highest_ts),
+                )
+            })
+            .collect();
+        let first_listens: Vec<i64> = listens.iter().map(|&(first, _)| first).collect();
+
+        let order = stack_order(&binned.scores, &binned.keys, &first_listens, &config.order);
+        let layers = stack(&binned.scores, &order, config.offset);
+
+        let n_bins = binned.n_bins();
+        let (first_bin, last_bin) = match n_bins {
+            0 => (0, 0),
+            n => (binned.bin_start(0), binned.bin_start(n - 1)),
+        };
+        let x_span = (last_bin - first_bin).max(1) as f64;
+        let xs: Vec<f64> = (0..n_bins)
+            .map(|idx| (binned.bin_start(idx) - first_bin) as f64 / x_span * config.width)
+            .collect();
+
+        let (y_min, y_max) = stack_extent(&layers).unwrap_or((0., 1.));
+        let y_scale = config.height / (y_max - y_min).max(f64::EPSILON);
+        let y_offset = config.height + y_min * y_scale;
+        let to_canvas = |ys: &[f64]| canvas_points(&xs, ys, config.width, y_offset, y_scale);
+
+        // previous areas by (is other series, key), if they were drawn the same way
+        let mut reusable: HashMap<SeriesId, (Vec<Point>, Vec<Point>, &TriangulatedArea)> =
+            HashMap::new();
+        // where each series was and how it was colored, the GL objects go by index
+        let mut previous_places: HashMap<SeriesId, (usize, String)> = HashMap::new();
+        if let Some(previous) = previous {
+            let same_drawing = previous.config.samples_per_segment == config.samples_per_segment
+                && previous.config.sample_mode == config.sample_mode;
+            for (key_idx, ((info, layer), area)) in previous
+                .keys
+                .iter()
+                .zip(&previous.layers)
+                .zip(&previous.areas)
+                .enumerate()
+            {
+                let to_canvas = |ys: &[f64]| {
+                    canvas_points(
+                        &previous.xs,
+                        ys,
+                        previous.config.width,
+                        previous.y_offset,
+                        previous.y_scale,
+                    )
+                };
+                let id = (info.is_other, info.key.clone());
+                if same_drawing {
+                    let outline = (to_canvas(&layer.y1), to_canvas(&layer.y0), area);
+                    reusable.insert(id.clone(), outline);
+                }
+                previous_places.insert(id, (key_idx, info.color.clone()));
+            }
+        }
+
+        let mut areas: Vec<TriangulatedArea> = Vec::with_capacity(layers.len());
+        let mut changed = vec![true; layers.len()];
+        for (key_idx, (key, layer)) in binned.keys.iter().zip(&layers).enumerate() {
+            let (top, bot) = (to_canvas(&layer.y1), to_canvas(&layer.y0));
+            let id = (binned.other_index() == Some(key_idx), key.clone());
+            match reusable.remove(&id) {
+                Some((prev_top, prev_bot, area)) if prev_top == top && prev_bot == bot => {
+                    areas.push(area.clone());
+                    changed[key_idx] = false;
+                }
+                _ => areas.push(TriangulatedArea::from_paths(
+                    curve_basis(&top),
+                    curve_basis(&bot),
+                    config.samples_per_segment,
+                    config.sample_mode,
+                )?),
+            }
+        }
+
+        let mut stack_indices = vec![0; order.len()];
+        for (stack_idx, &key_idx) in order.iter().enumerate() {
+            stack_indices[key_idx] = stack_idx;
+        }
+        let keys: Vec<KeyInfo> = binned
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(key_idx, key)| {
+                let is_other = binned.other_index() == Some(key_idx);
+                let (first_listen, last_listen) = listens[key_idx];
+                let color = if is_other {
+                    OTHER_COLOR
+                } else {
+                    let norm_ts = (first_listen - lowest_ts) as f64 / ts_span;
+                    interpolate_rainbow(norm_ts + key_noise(key) * config.color_noise)
+                };
+                KeyInfo {
+                    key: key.clone(),
+                    color: to_hex(color),
+                    pick_color: to_hex(pick_color(key_idx)),
+                    first_listen,
+                    last_listen,
+                    total_score: binned.scores[key_idx].iter().sum(),
+                    stack_index: stack_indices[key_idx],
+                    is_other,
+                }
+            })
+            .collect();
+        // a series that moved, e.g. when the top keys are picked again, changed too even if it
+        //   looks the same, something else was drawn where it is now
+        for (key_idx, (info, changed)) in keys.iter().zip(changed.iter_mut()).enumerate() {
+            let id = (info.is_other, info.key.clone());
+            if previous_places.get(&id) != Some(&(key_idx, info.color.clone())) {
+                *changed = true;
+            }
+        }
+
+        let graph = Self {
+            config,
+            binned,
+            layers,
+            areas,
+            keys,
+            filter_report,
+            xs,
+            y_scale,
+            y_offset,
+        };
+        Ok((graph, changed))
+    }
+
+    // keys summed into the "Other" series
+    pub fn folded_keys(&self) -> &[String] {
+        &self.binned.folded
+    }
+
+    pub fn key_for_pick_color(&self, color: Rgb) -> Option<&KeyInfo> {
+        let id = (color[0] as usize) << 16 | (color[1] as usize) << 8 | color[2] as usize;
+        id.checked_sub(1).and_then(|idx| self.keys.get(idx))
+    }
+}
+
+impl StreamgraphConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        // nan and infinite sizes fail too
+        let positive = |size: f64| size.is_finite() && size > 0.;
+        if !positive(self.width) || !positive(self.height) {
+            return Err("Chart size must be positive".into());
+        }
+        if self.samples_per_segment <= 0 {
+            return Err("samples_per_segment must be positive".into());
+        }
+        Ok(())
+    }
+}
+
+// one edge of a layer on the canvas, a single bin is drawn as a flat band across the whole
+//   chart, three points so its curve ends where any other chart's does
+fn canvas_points(xs: &[f64], ys: &[f64], width: f64, y_offset: f64, y_scale: f64) -> Vec<Point> {
+    let mut points: Vec<Point> = xs
+        .iter()
+        .zip(ys)
+        .map(|(&x, &y)| Point::new(x, y_offset - y * y_scale))
+        .collect();
+    if let [only] = points[..] {
+        points.extend([Point::new(width / 2., only.y), Point::new(width, only.y)]);
+    }
+    points
+}
+
+// normalizes and filters `records` and turns the ones left into points, noting first and last
+//   listens on the way
+pub(crate) fn collect_points(
+    records: &[ListenRecord],
+    config: &StreamgraphConfig,
+    spans: &mut ListenSpans,
+    artists: &mut ArtistNormalizer,
+) -> Result<(Vec<DataPoint>, FilterReport), String> {
+    let records = artists.normalize_all(records);
+    let (records, mut filter_report) = config.filter.apply(&records, config.group_by);
+    // sessions are found among the records that made it through the filter, and only within
+    //   this call, so a Dataset doesn't join sessions across batches
+    let session_starts: Option<Vec<i64>> = (config.score == ScoreMode::Sessions).then(|| {
+        let (sessions, session_of) = assign_sessions(&records, config.sessions);
+        session_of.iter().map(|&idx| sessions[idx].start).collect()
+    });
+    let mut points: Vec<DataPoint> = Vec::with_capacity(records.len());
+    for (idx, record) in records.into_iter().enumerate() {
+        let key = match config.group_by.key(record) {
+            Some(key) => key,
+            None => {
+                filter_report.reject(record, FilterReason::NoKey);
+                continue;
+            }
+        };
+        let session = session_starts.as_ref().map(|starts| starts[idx]);
+        points.push(
+            config
+                .score
+                .data_point(&key, record, config.time_zone, session)?,
+        );
+        spans.add(key, record);
+    }
+    Ok((points, filter_report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binning::OTHER_KEY;
+
+    const HOUR: i64 = 60 * 60 * 1000;
+    const DAY: i64 = 24 * HOUR;
+    // 2021-01-04, a monday
+    const START: i64 = 1_609_718_400_000;
+
+    fn play(artist: &str, day: i64, hour: i64, ms_played: u64) -> ListenRecord {
+        ListenRecord {
+            timestamp: START + day * DAY + hour * HOUR,
+            ms_played,
+            track_name: "track".into(),
+            artist_name: artist.into(),
+            ..Default::default()
+        }
+    }
+
+    // c every day, b first heard properly on day 3, a on day 1, d only ever skipped through
+    fn records() -> Vec<ListenRecord> {
+        let mut records: Vec<ListenRecord> =
+            (0..8).map(|day| play("c", day, 12, 2 * MINUTE)).collect();
+        records.extend([
+            play("b", 0, 18, MINUTE / 2),
+            play("b", 3, 12, 2 * MINUTE),
+            play("b", 5, 12, 2 * MINUTE),
+            play("a", 1, 12, 2 * MINUTE),
+            play("a", 6, 12, 2 * MINUTE),
+            play("d", 4, 12, MINUTE / 3),
+        ]);
+        records
+    }
+
+    fn config() -> StreamgraphConfig {
+        StreamgraphConfig {
+            time_step: TimeStep::Day,
+            order: StackOrder::FirstListen,
+            samples_per_segment: 4,
+            color_noise: 0.,
+            ..Default::default()
+        }
+    }
+
+    fn build(config: StreamgraphConfig) -> Streamgraph {
+        Streamgraph::build(&records(), config).unwrap()
+    }
+
+    fn keys(graph: &Streamgraph) -> Vec<&str> {
+        graph.keys.iter().map(|info| info.key.as_str()).collect()
+    }
+
+    fn stack_indices(graph: &Streamgraph) -> Vec<usize> {
+        graph.keys.iter().map(|info| info.stack_index).collect()
+    }
+
+    fn rainbow(day: i64) -> String {
+        to_hex(interpolate_rainbow(day as f64 / 7.))
+    }
+
+    #[test]
+    fn keys_are_in_order_of_first_play() {
+        let graph = build(config());
+        assert_eq!(keys(&graph), ["c", "b", "a", "d"]);
+        assert_eq!(graph.binned.n_bins(), 8);
+    }
+
+    #[test]
+    fn stack_index_follows_the_order() {
+        // b's first play is too short to count, so a goes below it
+        assert_eq!(stack_indices(&build(config())), [0, 2, 1, 3]);
+
+        let explicit = StackOrder::Explicit(vec!["d".into(), "a".into()]);
+        let graph = build(StreamgraphConfig {
+            order: explicit,
+            ..config()
+        });
+        assert_eq!(stack_indices(&graph), [2, 3, 1, 0]);
+    }
+
+    #[test]
+    fn first_and_last_listens() {
+        let graph = build(config());
+        let listens: Vec<(i64, i64)> = graph
+            .keys
+            .iter()
+            .map(|info| {
+                (
+                    (info.first_listen - START) / HOUR,
+                    (info.last_listen - START) / HOUR,
+                )
+            })
+            .collect();
+        assert_eq!(
+            listens,
+            [
+                (12, 7 * 24 + 12),
+                (3 * 24 + 12, 5 * 24 + 12),
+                (24 + 12, 6 * 24 + 12),
+                // no play is long enough, so the first one counts
+                (4 * 24 + 12, 4 * 24 + 12),
+            ]
+        );
+    }
+
+    #[test]
+    fn colors_follow_first_listens() {
+        let graph = build(config());
+        let colors: Vec<&str> = graph.keys.iter().map(|info| info.color.as_str()).collect();
+        assert_eq!(colors, [rainbow(0), rainbow(3), rainbow(1), rainbow(4)]);
+
+        for (idx, info) in graph.keys.iter().enumerate() {
+            assert_eq!(info.pick_color, to_hex(pick_color(idx)));
+            let found = graph.key_for_pick_color(pick_color(idx));
+            assert_eq!(found.map(|found| &found.key), Some(&info.key));
+        }
+        assert!(graph.key_for_pick_color([0, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn other_series_spans_the_keys_folded_into_it() {
+        let graph = build(StreamgraphConfig {
+            top_keys: Some(TopKeys::Total(2)),
+            ..config()
+        });
+        assert_eq!(keys(&graph), ["c", "b", OTHER_KEY]);
+        assert_eq!(graph.folded_keys(), ["a", "d"]);
+
+        let other = &graph.keys[2];
+        assert!(other.is_other);
+        assert_eq!(other.color, to_hex(OTHER_COLOR));
+        assert_eq!(other.first_listen, START + DAY + 12 * HOUR);
+        assert_eq!(other.last_listen, START + 6 * DAY + 12 * HOUR);
+        assert_eq!(other.total_score, (4 * MINUTE + MINUTE / 3) as f64);
+    }
+
+    #[test]
+    fn triangle_counts() {
+        // each curve segment is flattened into samples_per_segment columns and the straight
+        //   segments at either end into half as many, with two triangles between neighbouring
+        //   columns wherever the series isn't empty
+        // 8 bins make 7 curve segments
+        let graph = build(config());
+        assert_eq!(graph.areas.len(), graph.keys.len());
+        assert_eq!(graph.areas[0].triangles().len(), (2 * 2 + 7 * 4 - 1) * 2);
+
+        let graph = build(StreamgraphConfig {
+            samples_per_segment: 10,
+            ..config()
+        });
+        assert_eq!(graph.areas[0].triangles().len(), (2 * 5 + 7 * 10 - 1) * 2);
+        // the others are empty for part of the time and leave those columns out
+        for area in &graph.areas[1..] {
+            assert!(!area.triangles().is_empty());
+            assert!(area.triangles().len() < graph.areas[0].triangles().len());
+        }
+    }
+
+    #[test]
+    fn validate_rejects_bad_sizes_and_sampling() {
+        assert!(config().validate().is_ok());
+        let rejected = [
+            StreamgraphConfig {
+                width: 0.,
+                ..config()
+            },
+            StreamgraphConfig {
+                height: -1.,
+                ..config()
+            },
+            StreamgraphConfig {
+                width: f64::NAN,
+                ..config()
+            },
+            StreamgraphConfig {
+                height: f64::INFINITY,
+                ..config()
+            },
+            StreamgraphConfig {
+                samples_per_segment: 0,
+                ..config()
+            },
+        ];
+        for config in rejected {
+            assert!(config.validate().is_err());
+            assert!(Streamgraph::build(&records(), config).is_err());
+        }
+    }
+
+    #[test]
+    fn a_single_bin_is_drawn_across_the_chart() {
+        let records = [play("a", 0, 12, 2 * MINUTE), play("b", 0, 13, MINUTE)];
+        let graph = Streamgraph::build(&records, config()).unwrap();
+        assert_eq!(graph.binned.n_bins(), 1);
+        assert_eq!(graph.xs, [0.]);
+        for area in &graph.areas {
+            assert!(!area.triangles.is_empty());
+            let xs = area.triangles.iter().flatten().map(|pt| pt.x);
+            let (min, max) = xs.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), x| {
+                (min.min(x), max.max(x))
+            });
+            // the last sample is a little short of the end, as for more bins
+            assert_eq!(min, 0.);
+            assert!(max > 0.9 * graph.config.width);
+        }
+    }
+
+    #[test]
+    fn nothing_to_draw() {
+        let graph = Streamgraph::build(&[], config()).unwrap();
+        assert!(graph.keys.is_empty());
+        assert!(graph.areas.is_empty());
+    }
+}
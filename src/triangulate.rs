@@ -1,5 +1,8 @@
 use crate::{Line, Point, Triangle};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
 use svgtypes::{PathParser, PathSegment, PathSegment::*};
+use wasm_bindgen::prelude::*;
 
 #[derive(Debug, Clone, Copy)]
 enum Primitive {
@@ -58,22 +61,112 @@ struct Area {
 // ---Chain---<||||Area||||>----
 type Chain = Vec<Line>;
 
-pub fn gen_mesh(top_line: &str, bot_line: &str) -> Result<(Vec<Triangle>, Vec<Line>), String> {
-    // svg path should start at zero and move in the positive x direction
-    let top_segments = PathParser::from(top_line)
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| format!("Path Parsing Error: {:?}", e))?;
-    let bot_segments = PathParser::from(bot_line)
+// both control points of a cubic bezier segment
+type ControlPoints = (Point, Point);
+
+pub const DEFAULT_SAMPLES_PER_SEGMENT: i32 = 10;
+
+// steps in the per-segment table used to invert arc length back into bezier t
+const ARC_LENGTH_TABLE_STEPS: usize = 64;
+
+// how samples are spread along each curve segment
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SampleMode {
+    // evenly spaced in bezier parameter t, bunches up where control points are close
+    Uniform,
+    // evenly spaced in distance along the curve
+    ArcLength,
+}
+
+pub fn parse_path(line: &str) -> Result<Vec<PathSegment>, String> {
+    PathParser::from(line)
         .collect::<Result<Vec<_>, _>>()
-        .map_err(|e| format!("Path Parsing Error: {:?}", e))?;
+        .map_err(|e| format!("Path Parsing Error: {:?}", e))
+}
+
+// flattens both boundaries of a d3-area into lines from the top of each sample to the bottom
+// if `x_range` is given, only the segments overlapping it (plus one on either side) are sampled
+pub fn flatten_area(
+    top_segments: &[PathSegment],
+    bot_segments: &[PathSegment],
+    samples_per_segment: i32,
+    sample_mode: SampleMode,
+    x_range: Option<(f64, f64)>,
+) -> Result<Vec<Line>, String> {
+    // svg path should start at zero and move in the positive x direction
+    if top_segments.len() != bot_segments.len() {
+        return Err("Top and bottom paths must have the same number of segments".into());
+    }
+
+    let segment_range = match x_range {
+        Some((x_min, x_max)) => {
+            let top_range = segments_in_range(top_segments, x_min, x_max)?;
+            let bot_range = segments_in_range(bot_segments, x_min, x_max)?;
+            let (first, last) = match (top_range, bot_range) {
+                (Some(r1), Some(r2)) => (r1.0.min(r2.0), r1.1.max(r2.1)),
+                (Some(r), None) | (None, Some(r)) => r,
+                (None, None) => return Ok(Vec::new()),
+            };
+            // padded by a segment on either side so the samples reach past the edges of the range
+            first.saturating_sub(1).max(1)..(last + 2).min(top_segments.len())
+        }
+        None => 1..top_segments.len(),
+    };
 
-    let top_points = points_along_path(&top_segments, 10)?;
-    let bot_points = points_along_path(&bot_segments, 10)?;
+    Ok(flatten_segments(
+        top_segments,
+        bot_segments,
+        samples_per_segment,
+        sample_mode,
+        segment_range,
+    )?
+    .into_iter()
+    .flatten()
+    .collect())
+}
+
+// flattens `segment_range` of both boundaries, keeping the columns of each segment separate
+pub fn flatten_segments(
+    top_segments: &[PathSegment],
+    bot_segments: &[PathSegment],
+    samples_per_segment: i32,
+    sample_mode: SampleMode,
+    segment_range: Range<usize>,
+) -> Result<Vec<Vec<Line>>, String> {
+    let top_points = points_along_path(
+        top_segments,
+        samples_per_segment,
+        sample_mode,
+        segment_range.clone(),
+    )?;
+    let bot_points = points_along_path(
+        bot_segments,
+        samples_per_segment,
+        sample_mode,
+        segment_range,
+    )?;
 
     assert!(top_points.len() == bot_points.len());
 
+    Ok(top_points
+        .into_iter()
+        .zip(bot_points)
+        .map(|(top_seg, bot_seg)| {
+            assert!(top_seg.len() == bot_seg.len());
+            top_seg
+                .into_iter()
+                .zip(bot_seg)
+                .map(|(top_pt, bot_pt)| [top_pt, bot_pt])
+                .collect()
+        })
+        .collect())
+}
+
+pub fn mesh_columns(columns: &[Line]) -> (Vec<Triangle>, Vec<Line>) {
     let lines_and_points = pair_points(
-        top_points.into_iter().zip(bot_points.into_iter()),
+        columns.iter().map(|&[top_pt, bot_pt]| (top_pt, bot_pt)),
         f32::EPSILON as f64, // webgl represents points as f32
     );
     let lines: Vec<Line> = find_chains(&lines_and_points)
@@ -85,7 +178,16 @@ pub fn gen_mesh(top_line: &str, bot_line: &str) -> Result<(Vec<Triangle>, Vec<Li
         .flat_map(triangulate_area)
         .collect();
 
-    Ok((triangles, lines))
+    (triangles, lines)
+}
+
+// triangulates every pair of adjacent columns, including the ones with no measurable area,
+//   so the output always has (columns.len() - 1) * 2 triangles
+pub fn triangulate_columns(columns: &[Line]) -> Vec<Triangle> {
+    columns
+        .windows(2)
+        .flat_map(|slice| make_quad(slice[0], slice[1]))
+        .collect()
 }
 
 fn triangulate_area(area: Area) -> Vec<Triangle> {
@@ -105,7 +207,7 @@ fn triangulate_area(area: Area) -> Vec<Triangle> {
         .flat_map(|slice| make_quad(slice[0], slice[1]));
     first_tri
         .into_iter()
-        .chain(middle_tris.chain(last_tri.into_iter()))
+        .chain(middle_tris.chain(last_tri))
         .collect()
 }
 
@@ -187,7 +289,9 @@ fn pair_points(pairs: impl Iterator<Item = (Point, Point)>, dist_thresh: f64) ->
 fn points_along_path(
     path: &[PathSegment],
     samples_per_segment: i32,
-) -> Result<Vec<Point>, &'static str> {
+    sample_mode: SampleMode,
+    segment_range: Range<usize>,
+) -> Result<Vec<Vec<Point>>, &'static str> {
     assert!(samples_per_segment > 0);
 
     let init_pt = match path.first() {
@@ -198,46 +302,109 @@ fn points_along_path(
         None => return Ok(Vec::new()),
     };
 
-    let mut out_pts: Vec<Point> = Vec::new();
+    let mut out_pts: Vec<Vec<Point>> = Vec::new();
     let mut current_pt = init_pt;
-    for &seg in path.iter().skip(1) {
-        let mut inter_pts = interpolate_segment(current_pt, seg, samples_per_segment)?;
+    for (idx, &seg) in path.iter().enumerate().skip(1) {
+        if idx >= segment_range.end {
+            break;
+        }
+        if idx < segment_range.start {
+            current_pt = segment_controls(current_pt, seg)?.0;
+            continue;
+        }
+        let mut inter_pts = interpolate_segment(current_pt, seg, samples_per_segment, sample_mode)?;
         current_pt = inter_pts.pop().unwrap();
-        out_pts.extend(inter_pts);
+        out_pts.push(inter_pts);
     }
 
     Ok(out_pts)
 }
 
-//TODO: make absolute/relative agnostic
-fn interpolate_segment(
+// first and last index of the segments whose convex hull overlaps [x_min, x_max]
+fn segments_in_range(
+    path: &[PathSegment],
+    x_min: f64,
+    x_max: f64,
+) -> Result<Option<(usize, usize)>, &'static str> {
+    let mut current_pt = match path.first() {
+        Some(&MoveTo { x, y, .. }) => Point::new(x, y),
+        Some(_) => return Err("Path must begin with MoveTo"),
+        None => return Ok(None),
+    };
+
+    let mut range: Option<(usize, usize)> = None;
+    for (idx, &seg) in path.iter().enumerate().skip(1) {
+        let (dst_pt, control_pts) = segment_controls(current_pt, seg)?;
+        let xs = [current_pt.x, dst_pt.x]
+            .into_iter()
+            .chain(control_pts.into_iter().flat_map(|(c1, c2)| [c1.x, c2.x]));
+        let (seg_min, seg_max) = xs.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), x| {
+            (lo.min(x), hi.max(x))
+        });
+        if seg_min <= x_max && seg_max >= x_min {
+            range = Some(range.map_or((idx, idx), |(first, _)| (first, idx)));
+        }
+        current_pt = dst_pt;
+    }
+    Ok(range)
+}
+
+// destination point and bezier control points of a segment starting at `cur_pt`
+fn segment_controls(
     cur_pt: Point,
     segment: PathSegment,
-    mut n_samples: i32,
-) -> Result<Vec<Point>, &'static str> {
-    let (dst_pt, control_pts) = match segment {
-        CurveTo { x, y, x1, y1, x2, y2, .. } => {
+) -> Result<(Point, Option<ControlPoints>), &'static str> {
+    match segment {
+        CurveTo {
+            x,
+            y,
+            x1,
+            y1,
+            x2,
+            y2,
+            ..
+        } => {
             let dst_pt = Point::new(x, y);
             let control1 = Point::new(x1, y1);
             let control2 = Point::new(x2, y2);
-            (dst_pt, Some((control1, control2)))
+            Ok((dst_pt, Some((control1, control2))))
         }
-        LineTo { x, y, .. } => (Point::new(x, y), None),
-        HorizontalLineTo { x, .. } => (Point::new(x, cur_pt.y), None),
-        VerticalLineTo { y, .. } => (Point::new(cur_pt.x, y), None),
-        MoveTo { .. } => return Err("Subpaths not supported"),
+        LineTo { x, y, .. } => Ok((Point::new(x, y), None)),
+        HorizontalLineTo { x, .. } => Ok((Point::new(x, cur_pt.y), None)),
+        VerticalLineTo { y, .. } => Ok((Point::new(cur_pt.x, y), None)),
+        MoveTo { .. } => Err("Subpaths not supported"),
         _ => unimplemented!(),
-    };
+    }
+}
+
+//TODO: make absolute/relative agnostic
+fn interpolate_segment(
+    cur_pt: Point,
+    segment: PathSegment,
+    mut n_samples: i32,
+    sample_mode: SampleMode,
+) -> Result<Vec<Point>, &'static str> {
+    let (dst_pt, control_pts) = segment_controls(cur_pt, segment)?;
 
     // less samples needed for linear interpolation
     if control_pts.is_none() {
         n_samples /= 2
     }
 
+    // lines are already evenly spaced in t
+    let length_table = match (sample_mode, control_pts) {
+        (SampleMode::ArcLength, Some((c1, c2))) => Some(arc_length_table(cur_pt, dst_pt, c1, c2)),
+        _ => None,
+    };
+
     let step_size = 1. / n_samples as f64;
     Ok((0..n_samples)
         .map(|i| {
             let t = i as f64 * step_size;
+            let t = match &length_table {
+                Some(table) => t_at_length(table, t * table.last().unwrap()),
+                None => t,
+            };
             if let Some((c1, c2)) = control_pts {
                 cur_pt.berp(dst_pt, c1, c2, t)
             } else {
@@ -245,10 +412,44 @@ fn interpolate_segment(
             }
         })
         // apending dst_pt to make sure both t=0 and t=1 are returned
-        .chain([dst_pt].into_iter())
+        .chain([dst_pt])
         .collect())
 }
 
+// cumulative length along the curve at evenly spaced t, table[0] is always 0
+fn arc_length_table(start: Point, end: Point, c1: Point, c2: Point) -> Vec<f64> {
+    let mut prev_pt = start;
+    let mut length = 0.;
+    [0.].into_iter()
+        .chain((1..=ARC_LENGTH_TABLE_STEPS).map(|i| {
+            let t = i as f64 / ARC_LENGTH_TABLE_STEPS as f64;
+            let pt = start.berp(end, c1, c2, t);
+            length += prev_pt.distance(pt);
+            prev_pt = pt;
+            length
+        }))
+        .collect()
+}
+
+// inverse of the arc length table, linearly interpolated between entries
+fn t_at_length(table: &[f64], length: f64) -> f64 {
+    let steps = (table.len() - 1) as f64;
+    let idx = table.partition_point(|&l| l < length);
+    if idx == 0 {
+        return 0.;
+    }
+    if idx >= table.len() {
+        return 1.;
+    }
+    let (l1, l2) = (table[idx - 1], table[idx]);
+    let frac = if l2 > l1 {
+        (length - l1) / (l2 - l1)
+    } else {
+        0.
+    };
+    ((idx - 1) as f64 + frac) / steps
+}
+
 // cubic bezier interpolation
 fn berp(a: f64, b: f64, c: f64, d: f64, t: f64) -> f64 {
     let t2: f64 = t * t;
@@ -262,3 +463,80 @@ fn berp(a: f64, b: f64, c: f64, d: f64, t: f64) -> f64 {
 fn lerp(a: f64, b: f64, t: f64) -> f64 {
     (1. - t) * a + t * b
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(x1: f64, y1: f64, x2: f64, y2: f64, x: f64, y: f64) -> PathSegment {
+        CurveTo {
+            abs: true,
+            x1,
+            y1,
+            x2,
+            y2,
+            x,
+            y,
+        }
+    }
+
+    fn gaps(points: &[Point]) -> Vec<f64> {
+        points.windows(2).map(|w| w[0].distance(w[1])).collect()
+    }
+
+    #[test]
+    fn arc_length_table_measures_the_curve() {
+        // a straight line with its control points on it
+        let (start, end) = (Point::new(0., 0.), Point::new(3., 4.));
+        let table = arc_length_table(start, end, Point::new(0.6, 0.8), Point::new(2.4, 3.2));
+        assert_eq!(table.len(), ARC_LENGTH_TABLE_STEPS + 1);
+        assert_eq!(table[0], 0.);
+        assert!((table.last().unwrap() - 5.).abs() < 1e-9);
+        assert!(table.windows(2).all(|w| w[0] <= w[1]));
+
+        assert_eq!(t_at_length(&table, 0.), 0.);
+        assert_eq!(t_at_length(&table, 5.5), 1.);
+        // control points a third of the way along make it evenly paced already
+        assert!((t_at_length(&table, 2.5) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn arc_length_samples_are_evenly_spaced() {
+        // all the speed is at the end, x = 3t³
+        let start = Point::new(0., 0.);
+        let seg = curve(0., 0., 0., 0., 3., 0.);
+        let uniform = interpolate_segment(start, seg, 10, SampleMode::Uniform).unwrap();
+        let even = interpolate_segment(start, seg, 10, SampleMode::ArcLength).unwrap();
+        assert_eq!(even.len(), 11);
+        assert_eq!(even.first(), Some(&start));
+        assert_eq!(even.last(), Some(&Point::new(3., 0.)));
+        for gap in gaps(&even) {
+            assert!((gap - 0.3).abs() < 0.01, "{:?}", gaps(&even));
+        }
+        // t steps bunch up at the start
+        assert!(gaps(&uniform)[0] < 0.01);
+
+        // close to a quarter circle of radius 1
+        let k = 0.5523;
+        let seg = curve(0., k, 1. - k, 1., 1., 1.);
+        let even = interpolate_segment(Point::new(0., 0.), seg, 8, SampleMode::ArcLength).unwrap();
+        let gaps = gaps(&even);
+        let mean = gaps.iter().sum::<f64>() / gaps.len() as f64;
+        assert!(gaps.iter().all(|gap| (gap - mean).abs() < mean * 0.01));
+    }
+
+    #[test]
+    fn lines_are_sampled_the_same_either_way() {
+        let start = Point::new(0., 0.);
+        let seg = LineTo {
+            abs: true,
+            x: 4.,
+            y: 0.,
+        };
+        let uniform = interpolate_segment(start, seg, 8, SampleMode::Uniform).unwrap();
+        let even = interpolate_segment(start, seg, 8, SampleMode::ArcLength).unwrap();
+        assert_eq!(uniform, even);
+        // half as many samples as a curve
+        assert_eq!(gaps(&even), vec![1.; 4]);
+    }
+}
@@ -1,9 +1,10 @@
-use crate::{Line, Triangle};
+use crate::clip::Bounds;
+use crate::{Line, Point, Triangle};
 use cgmatrix as mat4;
 use wasm_bindgen::{JsCast, JsError, JsValue};
 use web_sys::{
-    HtmlCanvasElement, WebGl2RenderingContext as Gl, WebGlFramebuffer, WebGlProgram, WebGlShader,
-    WebGlVertexArrayObject,
+    HtmlCanvasElement, WebGl2RenderingContext as Gl, WebGlBuffer, WebGlFramebuffer, WebGlProgram,
+    WebGlShader, WebGlVertexArrayObject,
 };
 
 // TODO:
@@ -13,9 +14,14 @@ type Color = [u8; 3];
 
 struct RenderableObject {
     vao: WebGlVertexArrayObject,
+    buffer: WebGlBuffer,
     color: Color,
     draw_mode: u32,
     length: usize,
+    // vertices the buffer has room for, only larger than length for dynamic objects
+    capacity: usize,
+    is_morph: bool,
+    bounds: Bounds,
 }
 
 pub struct WebglState {
@@ -24,6 +30,7 @@ pub struct WebglState {
     frame_buffer: WebGlFramebuffer,
     objects: Vec<RenderableObject>,
     transform_matrix: mat4::Matrix44,
+    morph_t: f32,
     pub fb_width: i32,
     pub fb_height: i32,
 }
@@ -41,8 +48,10 @@ impl WebglState {
             r##"#version 300 es
  
         in vec2 a_position;
+        in vec2 a_positionTo;
 
         uniform mat4 u_matrix;
+        uniform float u_morphT;
 
         // out vec3 randColor;
         // float rand(vec2 co) {
@@ -50,7 +59,8 @@ impl WebglState {
         // }
 
         void main() {
-            gl_Position = vec4(u_matrix * vec4(a_position, 0.0, 1.0));
+            vec2 position = mix(a_position, a_positionTo, u_morphT);
+            gl_Position = vec4(u_matrix * vec4(position, 0.0, 1.0));
             // randColor = vec3(rand(a_position.xx),rand(a_position.yx),rand(a_position.xy));
         }
         "##,
@@ -123,6 +133,7 @@ impl WebglState {
             frame_buffer,
             objects: Vec::new(),
             transform_matrix: proj_mat(fb_width as f32, fb_height as f32),
+            morph_t: 0.,
             fb_width,
             fb_height,
         })
@@ -134,8 +145,6 @@ impl WebglState {
         lines: &[Line],
         color: Color,
     ) -> Result<(), String> {
-        let gl = &self.context;
-
         for (pts, draw_mode) in [
             (triangles.concat(), Gl::TRIANGLES),
             (lines.concat(), Gl::LINES),
@@ -143,47 +152,246 @@ impl WebglState {
         .into_iter()
         .filter(|(arr, _)| !arr.is_empty())
         {
-            let buffer = gl.create_buffer().ok_or("Failed to create buffer")?;
-            gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&buffer));
+            let flat_verts: Vec<f32> = pts
+                .into_iter()
+                .flat_map(|pt| [pt.x as f32, pt.y as f32])
+                .collect();
+            let object = self.create_object(&flat_verts, &["a_position"], color, draw_mode)?;
+            self.objects.push(object);
+        }
+        Ok(())
+    }
 
-            let vao = self
-                .context
-                .create_vertex_array()
-                .ok_or("Could not create vertex array object")?;
-            gl.bind_vertex_array(Some(&vao));
+    // `from` and `to` must have the same topology, vertex i of `from` moves to vertex i of `to`
+    pub fn add_morph_object(
+        &mut self,
+        from: &[Triangle],
+        to: &[Triangle],
+        color: Color,
+    ) -> Result<(), String> {
+        if from.len() != to.len() {
+            return Err("Morph targets must have the same number of triangles".into());
+        }
+        if from.is_empty() {
+            return Ok(());
+        }
 
-            let position_attr_loc = gl.get_attrib_location(&self.program, "a_position");
+        // interleaved as [from.x, from.y, to.x, to.y]
+        let flat_verts: Vec<f32> = from
+            .concat()
+            .into_iter()
+            .zip(to.concat())
+            .flat_map(|(src, dst)| [src.x as f32, src.y as f32, dst.x as f32, dst.y as f32])
+            .collect();
+        let mut object = self.create_object(
+            &flat_verts,
+            &["a_position", "a_positionTo"],
+            color,
+            Gl::TRIANGLES,
+        )?;
+        object.is_morph = true;
+        self.objects.push(object);
+        Ok(())
+    }
 
-            gl.vertex_attrib_pointer_with_i32(position_attr_loc as u32, 2, Gl::FLOAT, false, 0, 0);
+    // each attribute is a vec2, interleaved in `flat_verts` in the order they're given
+    fn create_object(
+        &self,
+        flat_verts: &[f32],
+        attributes: &[&str],
+        color: Color,
+        draw_mode: u32,
+    ) -> Result<RenderableObject, String> {
+        self.create_object_with_capacity(
+            flat_verts,
+            flat_verts.len(),
+            attributes,
+            color,
+            draw_mode,
+            Gl::STATIC_DRAW,
+        )
+    }
 
-            gl.enable_vertex_attrib_array(position_attr_loc as u32);
+    fn create_object_with_capacity(
+        &self,
+        flat_verts: &[f32],
+        capacity: usize,
+        attributes: &[&str],
+        color: Color,
+        draw_mode: u32,
+        usage: u32,
+    ) -> Result<RenderableObject, String> {
+        let gl = &self.context;
 
-            let flat_verts: Vec<f32> = pts
-                .into_iter()
-                .flat_map(|pt| [pt.x as f32, pt.y as f32])
-                .collect();
+        let buffer = gl.create_buffer().ok_or("Failed to create buffer")?;
+        gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&buffer));
+
+        let vao = self
+            .context
+            .create_vertex_array()
+            .ok_or("Could not create vertex array object")?;
+        gl.bind_vertex_array(Some(&vao));
+
+        let f32_size = std::mem::size_of::<f32>() as i32;
+        let stride = attributes.len() as i32 * 2 * f32_size;
+        for (i, attribute) in attributes.iter().enumerate() {
+            let attr_loc = gl.get_attrib_location(&self.program, attribute);
+            if attr_loc < 0 {
+                return Err(format!("Attribute {} not found", attribute));
+            }
+            gl.vertex_attrib_pointer_with_i32(
+                attr_loc as u32,
+                2,
+                Gl::FLOAT,
+                false,
+                stride,
+                i as i32 * 2 * f32_size,
+            );
+            gl.enable_vertex_attrib_array(attr_loc as u32);
+        }
 
+        let capacity = capacity.max(flat_verts.len());
+        if capacity > flat_verts.len() {
+            gl.buffer_data_with_i32(Gl::ARRAY_BUFFER, capacity as i32 * f32_size, usage);
+            buffer_sub_data(gl, 0, flat_verts);
+        } else {
             // unsafe to allocate memory until Float32Array::view() is dropped
             unsafe {
-                let positions_array_buf_view = js_sys::Float32Array::view(&flat_verts);
+                let positions_array_buf_view = js_sys::Float32Array::view(flat_verts);
 
                 gl.buffer_data_with_array_buffer_view(
                     Gl::ARRAY_BUFFER,
                     &positions_array_buf_view,
-                    Gl::STATIC_DRAW,
+                    usage,
                 );
             }
+        }
+        gl.bind_vertex_array(None);
+
+        let floats_per_vert = attributes.len() * 2;
+        Ok(RenderableObject {
+            vao,
+            buffer,
+            color,
+            draw_mode,
+            length: flat_verts.len() / floats_per_vert,
+            capacity: capacity / floats_per_vert,
+            is_morph: false,
+            bounds: Bounds::around(
+                flat_verts
+                    .chunks_exact(2)
+                    .map(|xy| Point::new(xy[0] as f64, xy[1] as f64)),
+            ),
+        })
+    }
+
+    // returns an id that update_dynamic_object() uses to rewrite the object's triangles
+    pub fn add_dynamic_object(
+        &mut self,
+        triangles: &[Triangle],
+        color: Color,
+    ) -> Result<usize, String> {
+        let flat_verts = flatten_triangles(triangles);
+        // leave room to append without reallocating
+        let capacity = (flat_verts.len() * 2).max(1024);
+        let object = self.create_object_with_capacity(
+            &flat_verts,
+            capacity,
+            &["a_position"],
+            color,
+            Gl::TRIANGLES,
+            Gl::DYNAMIC_DRAW,
+        )?;
+        self.objects.push(object);
+        Ok(self.objects.len() - 1)
+    }
 
-            self.objects.push(RenderableObject {
-                vao,
-                color,
-                draw_mode,
-                length: flat_verts.len() / 2,
-            });
+    // replaces the object's triangles from `first` on with `tail`, the ones before it stay as
+    //   they are, also on the gpu when the buffer has to grow
+    pub fn update_dynamic_object(
+        &mut self,
+        id: usize,
+        tail: &[Triangle],
+        first: usize,
+    ) -> Result<(), String> {
+        let gl = &self.context;
+        let obj = self.objects.get_mut(id).ok_or("Invalid object id")?;
+        if obj.is_morph || obj.draw_mode != Gl::TRIANGLES {
+            return Err("Object is not a dynamic area".into());
+        }
+        if first * 3 > obj.length {
+            return Err("Object has fewer triangles than the update starts at".into());
         }
+
+        let f32_size = std::mem::size_of::<f32>() as i32;
+        let n_verts = (first + tail.len()) * 3;
+        if n_verts > obj.capacity {
+            let capacity = n_verts.max(obj.capacity * 2);
+            let buffer = gl.create_buffer().ok_or("Failed to create buffer")?;
+            gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&buffer));
+            gl.buffer_data_with_i32(
+                Gl::ARRAY_BUFFER,
+                capacity as i32 * 2 * f32_size,
+                Gl::DYNAMIC_DRAW,
+            );
+            gl.bind_buffer(Gl::COPY_READ_BUFFER, Some(&obj.buffer));
+            gl.copy_buffer_sub_data_with_i32_and_i32_and_i32(
+                Gl::COPY_READ_BUFFER,
+                Gl::ARRAY_BUFFER,
+                0,
+                0,
+                first as i32 * 3 * 2 * f32_size,
+            );
+            gl.bind_buffer(Gl::COPY_READ_BUFFER, None);
+
+            // the vao still points at the old buffer
+            let attr_loc = gl.get_attrib_location(&self.program, "a_position");
+            gl.bind_vertex_array(Some(&obj.vao));
+            gl.vertex_attrib_pointer_with_i32(
+                attr_loc as u32,
+                2,
+                Gl::FLOAT,
+                false,
+                2 * f32_size,
+                0,
+            );
+            gl.bind_vertex_array(None);
+
+            gl.delete_buffer(Some(&obj.buffer));
+            obj.buffer = buffer;
+            obj.capacity = capacity;
+        } else {
+            gl.bind_buffer(Gl::ARRAY_BUFFER, Some(&obj.buffer));
+        }
+        buffer_sub_data(gl, first * 3 * 2, &flatten_triangles(tail));
+        obj.bounds = if first == 0 {
+            Bounds::around(tail.concat())
+        } else {
+            obj.bounds.union(&Bounds::around(tail.concat()))
+        };
+        obj.length = n_verts;
+        gl.bind_buffer(Gl::ARRAY_BUFFER, None);
+        Ok(())
+    }
+
+    pub fn set_object_color(&mut self, id: usize, color: Color) -> Result<(), String> {
+        let obj = self.objects.get_mut(id).ok_or("Invalid object id")?;
+        obj.color = color;
         Ok(())
     }
 
+    pub fn clear_objects(&mut self) {
+        let gl = &self.context;
+        for obj in self.objects.drain(..) {
+            gl.delete_vertex_array(Some(&obj.vao));
+            gl.delete_buffer(Some(&obj.buffer));
+        }
+    }
+
+    pub fn set_morph_t(&mut self, t: f32) {
+        self.morph_t = t.clamp(0., 1.);
+    }
+
     pub fn draw_objects(&self, draw_lines: bool) {
         let gl = &self.context;
 
@@ -192,16 +400,24 @@ impl WebglState {
         gl.clear(Gl::COLOR_BUFFER_BIT);
         let u_obj_color = gl.get_uniform_location(&self.program, "u_objColor");
         let u_matrix = gl.get_uniform_location(&self.program, "u_matrix");
+        let u_morph_t = gl.get_uniform_location(&self.program, "u_morphT");
         gl.uniform_matrix4fv_with_f32_array(u_matrix.as_ref(), false, &self.transform_matrix);
+        let view = self.visible_bounds().ok();
 
         for obj in self.objects.iter() {
             if !draw_lines && obj.draw_mode != Gl::TRIANGLES {
                 continue;
             }
+            if matches!(view, Some(view) if !view.intersects(&obj.bounds)) {
+                continue;
+            }
 
             let color = obj.color.map(|c| c as f32 / u8::MAX as f32);
             gl.bind_vertex_array(Some(&obj.vao));
             gl.uniform3f(u_obj_color.as_ref(), color[0], color[1], color[2]);
+            // a_positionTo is disabled for regular objects, so they must not be blended
+            let morph_t = if obj.is_morph { self.morph_t } else { 0. };
+            gl.uniform1f(u_morph_t.as_ref(), morph_t);
             gl.draw_arrays(obj.draw_mode, 0, obj.length as i32);
         }
         gl.bind_framebuffer(Gl::DRAW_FRAMEBUFFER, None);
@@ -238,6 +454,26 @@ impl WebglState {
         self.transform_matrix = mat4::matmul(origin, transformation);
     }
 
+    // area space rectangle that's currently mapped onto the canvas
+    pub fn visible_bounds(&self) -> Result<Bounds, String> {
+        // only the 2d affine part of the column-major transform matters for points at z = 0
+        let m = self.transform_matrix.map(|v| v as f64);
+        let det = m[0] * m[5] - m[4] * m[1];
+        if det == 0. {
+            return Err("Transform is not invertible".into());
+        }
+        let inverse = |clip_x: f64, clip_y: f64| {
+            let (x, y) = (clip_x - m[12], clip_y - m[13]);
+            Point::new((m[5] * x - m[4] * y) / det, (m[0] * y - m[1] * x) / det)
+        };
+        Ok(Bounds::around([
+            inverse(-1., -1.),
+            inverse(1., -1.),
+            inverse(-1., 1.),
+            inverse(1., 1.),
+        ]))
+    }
+
     pub fn read_pixels(&self, x: i32, y: i32, w: i32, h: i32) -> Result<Vec<[u8; 4]>, String> {
         let gl = &self.context;
         gl.bind_framebuffer(Gl::FRAMEBUFFER, None);
@@ -265,6 +501,28 @@ impl WebglState {
     }
 }
 
+fn flatten_triangles(triangles: &[Triangle]) -> Vec<f32> {
+    triangles
+        .iter()
+        .flatten()
+        .flat_map(|pt| [pt.x as f32, pt.y as f32])
+        .collect()
+}
+
+// writes into the currently bound array buffer, `offset` is in floats
+fn buffer_sub_data(gl: &Gl, offset: usize, flat_verts: &[f32]) {
+    let f32_size = std::mem::size_of::<f32>();
+    // unsafe to allocate memory until Float32Array::view() is dropped
+    unsafe {
+        let array_buf_view = js_sys::Float32Array::view(flat_verts);
+        gl.buffer_sub_data_with_i32_and_array_buffer_view(
+            Gl::ARRAY_BUFFER,
+            (offset * f32_size) as i32,
+            &array_buf_view,
+        );
+    }
+}
+
 fn get_webgl_context(canvas_id: &str) -> Result<Gl, JsValue> {
     let document = web_sys::window().unwrap().document().unwrap();
     let canvas = document
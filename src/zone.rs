@@ -0,0 +1,114 @@
+use chrono::{DateTime, Duration, FixedOffset, LocalResult, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// the listener's time zone, bins and hour-of-day views follow its wall clock
+// written as "UTC", a fixed offset like "+05:30", or an IANA name like "Europe/Berlin"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Zone {
+    #[default]
+    Utc,
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl Zone {
+    pub fn parse(zone: &str) -> Result<Self, String> {
+        let zone = zone.trim();
+        if zone.eq_ignore_ascii_case("utc") || zone.eq_ignore_ascii_case("z") {
+            return Ok(Self::Utc);
+        }
+        if zone.starts_with('+') || zone.starts_with('-') {
+            return parse_offset(zone).map(Self::Fixed);
+        }
+        zone.parse::<Tz>()
+            .map(Self::Named)
+            .map_err(|_| format!("Unknown time zone {:?}", zone))
+    }
+
+    // wall clock time at `timestamp` (ms since epoch)
+    pub fn to_local(&self, timestamp: i64) -> Result<NaiveDateTime, String> {
+        let utc = DateTime::from_timestamp_millis(timestamp)
+            .ok_or_else(|| format!("Timestamp {} out of range", timestamp))?
+            .naive_utc();
+        utc.checked_add_signed(Duration::seconds(self.utc_offset_at(&utc) as i64))
+            .ok_or_else(|| format!("Timestamp {} out of range", timestamp))
+    }
+
+    // ms since epoch of a wall clock time
+    // times that happen twice when clocks go back resolve to the first one, times skipped
+    //   when clocks go forward resolve to the moment of the jump
+    pub fn from_local(&self, local: NaiveDateTime) -> Result<i64, String> {
+        let offset = match self {
+            Self::Utc => 0,
+            Self::Fixed(offset) => offset.local_minus_utc(),
+            Self::Named(tz) => match tz.offset_from_local_datetime(&local) {
+                LocalResult::Single(offset) | LocalResult::Ambiguous(offset, _) => {
+                    offset.fix().local_minus_utc()
+                }
+                // skipped times are in a gap right after a transition, so the offset from
+                //   before it puts them at the transition or past it
+                LocalResult::None => {
+                    let before = local - Duration::days(1);
+                    tz.offset_from_utc_datetime(&before).fix().local_minus_utc()
+                }
+            },
+        };
+        local
+            .checked_sub_signed(Duration::seconds(offset as i64))
+            .map(|utc| utc.and_utc().timestamp_millis())
+            .ok_or_else(|| format!("Time {} out of range", local))
+    }
+
+    fn utc_offset_at(&self, utc: &NaiveDateTime) -> i32 {
+        match self {
+            Self::Utc => 0,
+            Self::Fixed(offset) => offset.local_minus_utc(),
+            Self::Named(tz) => tz.offset_from_utc_datetime(utc).fix().local_minus_utc(),
+        }
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Utc => f.write_str("UTC"),
+            Self::Fixed(offset) => write!(f, "{}", offset),
+            Self::Named(tz) => f.write_str(tz.name()),
+        }
+    }
+}
+
+impl TryFrom<String> for Zone {
+    type Error = String;
+
+    fn try_from(zone: String) -> Result<Self, String> {
+        Self::parse(&zone)
+    }
+}
+
+impl From<Zone> for String {
+    fn from(zone: Zone) -> Self {
+        zone.to_string()
+    }
+}
+
+// "+05:30", "-0800", "+5"
+fn parse_offset(offset: &str) -> Result<FixedOffset, String> {
+    let invalid = || format!("Invalid UTC offset {:?}", offset);
+    let (sign, digits) = offset.split_at(1);
+    let sign = if sign == "-" { -1 } else { 1 };
+    let (hours, minutes) = match digits.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if digits.len() > 2 => digits.split_at(digits.len() - 2),
+        None => (digits, "0"),
+    };
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    if minutes >= 60 {
+        return Err(invalid());
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
+}
@@ -1,12 +1,25 @@
 import { initDropArea } from './file-upload';
 import { drawGraph } from './graph';
 
+// ListenRecord from records.rs, only ever made by the parsers in rust so
+//   every file's timestamps are read the same way
 export interface TrackData {
     trackName: string;
     artistName: string;
     albumName?: string;
     msPlayed: number;
+    // ms since epoch, utc
     timestamp: number;
+    kind?: 'music' | 'podcast';
+    // everything below is only in the extended history
+    platform?: string;
+    connCountry?: string;
+    reasonStart?: string;
+    reasonEnd?: string;
+    shuffle?: boolean;
+    offline?: boolean;
+    skipped?: boolean;
+    incognitoMode?: boolean;
 }
 
 initDropArea((tracks, extTracks) => {
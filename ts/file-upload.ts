@@ -1,15 +1,30 @@
 import { TrackData } from './app';
-
-enum FileType {
-    Extended,
-    Regular,
-    Invalid
-}
+import {
+    is_apple_music_play_activity,
+    parse_any_streaming_history,
+    parse_apple_music_play_activity,
+    parse_lastfm_scrobbles_csv,
+    parse_spotify_data_zip,
+} from '../pkg';
 
 interface Counter {
     count: number;
 }
 
+// ParsedHistory from parse.rs, HistoryFile from archive.rs adds a name
+interface ParsedHistory {
+    records: TrackData[];
+    report: {
+        format: string;
+    };
+}
+
+// HistoryArchive from archive.rs
+interface HistoryArchive {
+    files: ParsedHistory[];
+    failed: { name: string; error: string }[];
+}
+
 export function initDropArea(
     onUploadEnd: (allTracks: TrackData[], allExtTracks: TrackData[]) => void
 ) {
@@ -36,35 +51,21 @@ export function initDropArea(
     });
     dropArea.addEventListener('drop', (e: DragEvent) => {
         if (e.dataTransfer != null) {
-            [...e.dataTransfer.items].forEach((item) => {
-                const entry = item.webkitGetAsEntry();
-                if (entry.isDirectory) {
-                    (entry as FileSystemDirectoryEntry)
-                        .createReader()
-                        .readEntries((entries) => {
-                            entries.forEach((dirEntry) => {
-                                (dirEntry as FileSystemFileEntry).file((f) =>
-                                    ingestFile(
-                                        f,
-                                        allTracks,
-                                        allExtTracks,
-                                        readCount,
-                                        onUploadEnd
-                                    )
-                                );
-                            });
-                        });
-                } else if (entry.isFile) {
-                    (entry as FileSystemFileEntry).file((f) =>
-                        ingestFile(
-                            f,
-                            allTracks,
-                            allExtTracks,
-                            readCount,
-                            onUploadEnd
-                        )
-                    );
+            const ingest = (f: File) =>
+                ingestFile(f, allTracks, allExtTracks, readCount, onUploadEnd);
+            // a drop with no history files in it leaves the page as it is
+            const onReadEnd = () => {
+                if (allTracks.length + allExtTracks.length > 0) {
+                    onUploadEnd(allTracks, allExtTracks);
                 }
+            };
+            [...e.dataTransfer.items].forEach((item) => {
+                ingestEntry(
+                    item.webkitGetAsEntry(),
+                    ingest,
+                    readCount,
+                    onReadEnd
+                );
             });
         }
     });
@@ -81,16 +82,75 @@ export function initDropArea(
     });
 }
 
-// TODO: maybe validate content instead of relying on filename
-function checkFilename(filename: string): FileType {
-    if (filename.match(/^endsong_\d+\.json$/) != null) {
-        return FileType.Extended;
-    } else if (filename.match(/^StreamingHistory\d+\.json$/) != null) {
-        return FileType.Regular;
+// files, and folders at any depth
+// every entry counts as a read until it's handed to `ingest`, and a folder
+//   until all of its entries are found, so `onReadEnd` doesn't fire early
+function ingestEntry(
+    entry: FileSystemEntry,
+    ingest: (f: File) => void,
+    readCount: Counter,
+    onReadEnd: () => void
+) {
+    const finishRead = () => {
+        readCount.count--;
+        if (readCount.count == 0) {
+            onReadEnd();
+        }
+    };
+    if (entry.isFile) {
+        readCount.count++;
+        (entry as FileSystemFileEntry).file((f) => {
+            ingest(f);
+            finishRead();
+        }, finishRead);
+    } else if (entry.isDirectory) {
+        readCount.count++;
+        const reader = (entry as FileSystemDirectoryEntry).createReader();
+        // readEntries() hands out a batch at a time, the last one is empty
+        const readBatch = () => {
+            reader.readEntries((entries) => {
+                if (entries.length == 0) {
+                    finishRead();
+                    return;
+                }
+                entries.forEach((child) =>
+                    ingestEntry(child, ingest, readCount, onReadEnd)
+                );
+                readBatch();
+            }, finishRead);
+        };
+        readBatch();
     }
-    return FileType.Invalid;
 }
 
+// my_spotify_data.zip or a google takeout, the history files in it are found
+//   by their contents, see archive.rs
+function ingestZip(
+    file: File,
+    allTracks: TrackData[],
+    allExtTracks: TrackData[],
+    readCount: Counter,
+    onUploadFinish: (allTracks: TrackData[], allExtTracks: TrackData[]) => void
+) {
+    readHistories(
+        file,
+        (bytes) => {
+            const archive: HistoryArchive = parse_spotify_data_zip(bytes);
+            archive.failed.forEach(({ name, error }) => {
+                console.error(`couldn't read ${name} in ${file.name}:`, error);
+            });
+            return archive.files;
+        },
+        allTracks,
+        allExtTracks,
+        readCount,
+        onUploadFinish
+    );
+}
+
+// a .csv is apple's play activity if it has its header, otherwise last.fm
+//   scrobbles, any other file is recognized by its contents whatever it's
+//   called, files that aren't a streaming history are ignored
 function ingestFile(
     file: File,
     allTracks: TrackData[],
@@ -98,81 +158,69 @@ function ingestFile(
     readCount: Counter,
     onUploadFinish: (allTracks: TrackData[], allExtTracks: TrackData[]) => void
 ) {
-    let isExtended: boolean;
-    switch (checkFilename(file.name)) {
-        case FileType.Extended: {
-            isExtended = true;
-            break;
-        }
-        case FileType.Regular: {
-            isExtended = false;
-            break;
-        }
-        case FileType.Invalid:
-            return;
+    if (file.name.toLowerCase().endsWith('.zip')) {
+        ingestZip(file, allTracks, allExtTracks, readCount, onUploadFinish);
+        return;
     }
-    const tracks = isExtended ? allExtTracks : allTracks;
-    const parseFn = isExtended ? parseExtTrackData : parseTrackData;
+    const isCsv = file.name.toLowerCase().endsWith('.csv');
+    readHistories(
+        file,
+        (bytes) => {
+            if (isCsv && is_apple_music_play_activity(bytes)) {
+                return [parse_apple_music_play_activity(bytes)];
+            }
+            if (isCsv) {
+                return [parse_lastfm_scrobbles_csv(bytes)];
+            }
+            const parsed: ParsedHistory | null =
+                parse_any_streaming_history(bytes);
+            return parsed == null ? [] : [parsed];
+        },
+        allTracks,
+        allExtTracks,
+        readCount,
+        onUploadFinish
+    );
+}
+
+// reads `file` and adds the records of every history `parse` finds in it
+function readHistories(
+    file: File,
+    parse: (bytes: Uint8Array) => ParsedHistory[],
+    allTracks: TrackData[],
+    allExtTracks: TrackData[],
+    readCount: Counter,
+    onUploadFinish: (allTracks: TrackData[], allExtTracks: TrackData[]) => void
+) {
     const reader = new FileReader();
     readCount.count++;
     reader.onloadend = () => {
-        parseFn(reader.result as string)
-            .filter((track) => track.msPlayed > 0 && track.timestamp > 0)
-            .forEach((track) => {
-                tracks.push(track);
+        try {
+            const histories = parse(
+                new Uint8Array(reader.result as ArrayBuffer)
+            );
+            histories.forEach((history) => {
+                // apple and youtube music plays go with the regular
+                //   history, only extended rows add anything to a play
+                const tracks =
+                    history.report.format == 'extended'
+                        ? allExtTracks
+                        : allTracks;
+                history.records
+                    .filter(
+                        (track) => track.msPlayed > 0 && track.timestamp > 0
+                    )
+                    .forEach((track) => {
+                        tracks.push(track);
+                    });
             });
+        } catch (err) {
+            console.error(`couldn't read ${file.name}:`, err);
+        }
         readCount.count--;
         if (readCount.count == 0) {
             onUploadFinish(allTracks, allExtTracks);
         }
     };
-    reader.readAsText(file);
-}
-
-// normal streaming history (StreamingHistoryN.json)
-function parseTrackData(rawJson: string): TrackData[] {
-    const parsed = JSON.parse(rawJson);
-
-    const out: TrackData[] = [];
-    parsed.forEach((elem) => {
-        if (
-            elem.endTime != null &&
-            elem.msPlayed != null &&
-            elem.trackName != null &&
-            elem.artistName != null
-        ) {
-            out.push({
-                timestamp: Date.parse(elem.endTime) - elem.msPlayed,
-                msPlayed: elem.msPlayed,
-                trackName: elem.trackName,
-                artistName: elem.artistName
-            });
-        }
-    });
-    return out;
-}
-
-// extended streaming history (endsong_N.json)
-function parseExtTrackData(rawJson: string): TrackData[] {
-    const parsed = JSON.parse(rawJson);
-
-    const out: TrackData[] = [];
-    parsed.forEach((elem) => {
-        if (
-            elem.ts != null &&
-            elem.ms_played != null &&
-            elem.master_metadata_album_album_name != null &&
-            elem.master_metadata_album_artist_name != null &&
-            elem.master_metadata_track_name != null
-        ) {
-            out.push({
-                timestamp: Date.parse(elem.ts) - elem.ms_played,
-                msPlayed: elem.ms_played,
-                trackName: elem.master_metadata_track_name,
-                artistName: elem.master_metadata_album_artist_name,
-                albumName: elem.master_metadata_album_album_name
-            });
-        }
-    });
-    return out;
+    reader.readAsArrayBuffer(file);
 }
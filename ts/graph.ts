@@ -1,6 +1,11 @@
 import { TrackData } from './app';
 import * as d3 from 'd3';
-import { WebglCtx, TriangulatedArea } from '../pkg';
+import {
+    WebglCtx,
+    DatasetCtx,
+    DatasetObjects,
+    merge_streaming_history
+} from '../pkg';
 
 const SECOND = 1000;
 const MINUTE = SECOND * 60;
@@ -9,69 +14,30 @@ const SELECTION_STROKE_W = 1.5;
 const OVERLAY_LINE_W = 2;
 const TRANSITION_DUR = SECOND * 0.8;
 
-interface ScoresAtTime {
-    scores: {
-        [key: string]: number;
-    };
-    timestamp: number;
-}
-
-interface RawDataPoint {
+// KeyInfo from streamgraph.rs
+interface KeyInfo {
     key: string;
-    score: number;
-    timestamp: number;
+    color: string;
+    pickColor: string;
+    firstListen: number;
+    lastListen: number;
+    totalScore: number;
+    stackIndex: number;
+    isOther: boolean;
 }
 
-interface PopularityOverTime {
-    scores: ScoresAtTime[];
-    keys: string[];
+// set up by the first drawGraph() call, later uploads go in the same chart
+interface Chart {
+    dataset: DatasetCtx;
+    canvasCtx: WebglCtx;
+    offscreenCtx: WebglCtx;
+    objects: DatasetObjects;
+    pickingObjects: DatasetObjects;
+    // call after the dataset changed
+    refresh: () => void;
 }
 
-function popularityOverTime(
-    tracks: RawDataPoint[],
-    timeStepMs: number
-): PopularityOverTime {
-    if (tracks.length == 0) {
-        return {
-            scores: [],
-            keys: []
-        };
-    }
-
-    const sortedTracks = [...tracks].sort((a, b) => a.timestamp - b.timestamp);
-    const allKeys = [...new Set(sortedTracks.map((track) => track.key))];
-
-    let lastIdx = 0;
-    let currentTime = sortedTracks[0].timestamp;
-    const endTime = sortedTracks[sortedTracks.length - 1].timestamp;
-    const scores: ScoresAtTime[] = [];
-    while (currentTime <= endTime) {
-        const chunkScores: ScoresAtTime = {
-            scores: Object.fromEntries(allKeys.map((i) => [i, 0])),
-            timestamp: sortedTracks[lastIdx].timestamp
-        };
-
-        for (const [idx, track] of sortedTracks
-            .slice(lastIdx, undefined)
-            .entries()) {
-            if (
-                track.timestamp >= currentTime &&
-                track.timestamp <= currentTime + timeStepMs
-            ) {
-                chunkScores.scores[track.key] += track.score;
-            } else {
-                lastIdx += idx;
-                scores.push(chunkScores);
-                break;
-            }
-        }
-        currentTime += timeStepMs;
-    }
-    return {
-        scores: scores,
-        keys: allKeys
-    };
-}
+let chart: Chart | null = null;
 
 function color2hex(color: number[]): string {
     return (
@@ -85,13 +51,6 @@ function color2hex(color: number[]): string {
     );
 }
 
-function randColor(): string {
-    return (
-        '#' +
-        (0x1000000 + Math.random() * 0xffffff).toString(16).substring(1, 7)
-    );
-}
-
 function clamp(val: number, min: number, max: number): number {
     return Math.min(Math.max(val, min), max);
 }
@@ -108,36 +67,36 @@ function unpackRgba(color: number): number[] {
     return [r, g, b, a];
 }
 
+// gets every track uploaded so far, the dataset works out what changed
 export function drawGraph(allTracks: TrackData[], allExtTracks: TrackData[]) {
+    // plays in both histories are only counted once, see merge.rs
+    const merged: TrackData[] = merge_streaming_history(
+        allExtTracks,
+        allTracks
+    ).records;
+
+    if (chart == null) {
+        chart = createChart();
+    }
+    console.log('starting triangulate...');
+    const t0 = performance.now();
+    chart.dataset.replace_records(merged);
+    chart.canvasCtx.update_dataset(chart.objects, chart.dataset);
+    chart.offscreenCtx.update_dataset(chart.pickingObjects, chart.dataset);
+    chart.dataset.mark_synced();
+    const t1 = performance.now();
+    console.log(`triangulate took ${t1 - t0} milliseconds.`);
+
+    chart.refresh();
+}
+
+function createChart(): Chart {
     const WIDTH = Math.floor(window.innerWidth * 0.9);
     const HEIGHT = Math.floor(WIDTH / 3);
 
-    // if both extended and regular track data are uploaded, use the one with more entries
-    const rawData: RawDataPoint[] = (
-        allExtTracks.length > allTracks.length ? allExtTracks : allTracks
-    )
-        .map((d) => ({
-            key: d.artistName,
-            score: d.msPlayed,
-            timestamp: d.timestamp
-        }))
-        .sort((a, b) => a.timestamp - b.timestamp);
-    const popularity = popularityOverTime(
-        rawData,
-        2629800000 /* ~1 month, gives nice results */
-    );
-
-    // dataPoint formatted like https://github.com/d3/d3-shape#stack
-    // interface {
-    //     [key: string]: number;
-    //     __timestamp: number;
-    // }
-    const data = popularity.scores.map((scores: ScoresAtTime) => {
-        const dataPoint = scores.scores;
-        dataPoint.__timestamp = scores.timestamp;
-        return dataPoint;
-    });
-    const keys = popularity.keys;
+    // the rest of StreamgraphConfig is left at its defaults: monthly bins,
+    //   wiggle offset, inside out order and colors by first listen
+    const dataset = new DatasetCtx({ width: WIDTH, height: HEIGHT });
 
     // TODO: margin: https://bl.ocks.org/mbostock/3019563
 
@@ -157,26 +116,10 @@ export function drawGraph(allTracks: TrackData[], allExtTracks: TrackData[]) {
         .attr('height', HEIGHT);
     const offscreenCtx = new WebglCtx('offscreenCanvas');
 
-    const colorIdToKeyMap: Map<string, string> = new Map();
-    const keyToColorIdMap: Map<string, string> = new Map();
-    keys.forEach((key) => {
-        let color: string = randColor();
-        while (colorIdToKeyMap.has(color)) {
-            color = randColor();
-        }
-        keyToColorIdMap.set(key, color);
-        colorIdToKeyMap.set(color, key);
-    });
-    const stack = d3
-        .stack()
-        .offset(d3.stackOffsetWiggle)
-        .order(d3.stackOrderInsideOut)
-        .keys(keys);
-    const stackedData = stack(data);
-    const dataLookup = new Map();
-    stackedData.forEach((datum) => {
-        dataLookup.set(datum.key, datum);
-    });
+    const objects = canvasCtx.add_dataset(dataset, false);
+    const pickingObjects = offscreenCtx.add_dataset(dataset, true);
+
+    const keys = (): KeyInfo[] => dataset.keys();
 
     const overlay = d3
         .select('#d3')
@@ -186,91 +129,22 @@ export function drawGraph(allTracks: TrackData[], allExtTracks: TrackData[]) {
         .attr('height', HEIGHT);
     const selectionGroup = overlay.append('g').attr('id', 'selection');
 
-    const x = d3
-        .scaleTime()
-        .domain(d3.extent(data, (d) => new Date(d.__timestamp)))
-        .range([0, WIDTH]);
+    // bins are placed by their start, the last one at the right edge
+    const x = d3.scaleTime().range([0, WIDTH]);
     const xAxis = d3.axisBottom(x);
-    const y = d3
-        .scaleLinear()
-        .domain([
-            d3.min(stackedData, (series) => d3.min(series, (d) => d[0])),
-            d3.max(stackedData, (series) => d3.max(series, (d) => d[1]))
-        ])
-        .range([HEIGHT, 0]);
 
-    // TODO: speed this up
-    const firstListenedColor = (
-        key: string,
-        minAcceptedScore: number,
-        colorNoiseAmt: number
-    ) => {
-        const lowestTs = rawData[0].timestamp;
-        const highestTs = rawData[rawData.length - 1].timestamp;
-        const firstListened = rawData.find(
-            (datum) => datum.key == key && datum.score > minAcceptedScore
-        );
-
-        // if we don't find an entry with a score > minAcceptedScore, find the first occurrence of the key regardless of score
-        const firstListenedTs = firstListened
-            ? firstListened.timestamp
-            : rawData.find((datum) => datum.key == key).timestamp;
-
-        const normTs = (firstListenedTs - lowestTs) / (highestTs - lowestTs);
-        const noise = (Math.random() - 0.5) * 2 * colorNoiseAmt;
-
-        // return d3.interpolateRdYlGn(normTs);
-        return d3.interpolateRainbow(normTs + noise);
-        // return d3.interpolateTurbo(normTs);
-        // return d3.interpolateCool(normTs);
+    // path of the series at `keyIdx`, in canvas pixels like its triangles
+    const outline = (keyIdx: number): string => {
+        const xs = dataset.xs();
+        const ys = dataset.outline_ys(keyIdx);
+        const n = xs.length;
+        return d3
+            .area<number>()
+            .x((i) => xs[i])
+            .y0((i) => ys[i])
+            .y1((i) => ys[n + i])
+            .curve(d3.curveBasis)(d3.range(n));
     };
-    const color = d3
-        .scaleOrdinal()
-        .domain(keys)
-        .range(keys.map((k) => firstListenedColor(k, MINUTE, 0.04)));
-
-    const area = d3
-        .area()
-        .x((d: any) => x(d.data.__timestamp))
-        .y0((d) => y(d[0]))
-        .y1((d) => y(d[1]))
-        .curve(d3.curveBasis);
-    const bottomLine = d3
-        .line()
-        .x((d: any) => x(d.data.__timestamp))
-        .y((d) => y(d[0]))
-        .curve(d3.curveBasis);
-    const topLine = d3
-        .line()
-        .x((d: any) => x(d.data.__timestamp))
-        .y((d) => y(d[1]))
-        .curve(d3.curveBasis);
-
-    // https://bocoup.com/blog/d3js-and-canvas
-    const detachedContainer = document.createElement('custom');
-    const dataContainer = d3.select(detachedContainer);
-    const dataBinding = dataContainer
-        .selectAll('custom.node')
-        .data(stackedData)
-        .enter()
-        .append('custom')
-        .classed('node', true)
-        .attr('fill', (d) => String(color(d.key)));
-
-    console.log('starting triangulate...');
-    const t0 = performance.now();
-    dataBinding.each(function (d: any) {
-        const node = d3.select(this);
-        const top: string = topLine.context(null)(d);
-        const bot: string = bottomLine.context(null)(d);
-        const color = node.attr('fill');
-        const triangulated = new TriangulatedArea(top, bot);
-        canvasCtx.add_area(triangulated, color);
-        offscreenCtx.add_area(triangulated, keyToColorIdMap.get(d.key));
-        triangulated.free();
-    });
-    const t1 = performance.now();
-    console.log(`triangulate took ${t1 - t0} milliseconds.`);
 
     const lineGroup = overlay.append('g');
     const lineMask = lineGroup.append('mask').attr('id', 'lineMask');
@@ -319,35 +193,35 @@ export function drawGraph(allTracks: TrackData[], allExtTracks: TrackData[]) {
     };
 
     const updateSelectionOverlay = (
-        key: string,
+        keyIdx: number,
         mouseX: number,
         curTransform: d3.ZoomTransform
     ) => {
         mouseOverLine.attr('visibility', 'visible'); // hidden on each mouseOut
 
         const zoomedX = curTransform.invertX(mouseX);
-        const fltIdx = (zoomedX / WIDTH) * (data.length - 1);
-        if (fltIdx >= 0 && fltIdx <= data.length - 1) {
-            const lerpAmt = fltIdx - Math.floor(fltIdx);
+        const xs = dataset.xs();
+        const idx = d3.bisectRight(xs, zoomedX) - 1;
+        if (idx >= 0 && idx < xs.length) {
+            const scores = dataset.scores(keyIdx);
+            const binEdges = dataset.bin_edges();
+            const nextIdx = Math.min(idx + 1, xs.length - 1);
+            const binWidth = xs[nextIdx] - xs[idx];
+            const lerpAmt =
+                binWidth > 0 ? clamp((zoomedX - xs[idx]) / binWidth, 0, 1) : 0;
 
             const minsListened = Math.round(
-                lerp(
-                    data[Math.floor(fltIdx)][key],
-                    data[Math.ceil(fltIdx)][key],
-                    lerpAmt
-                ) / MINUTE
+                lerp(scores[idx], scores[nextIdx], lerpAmt) / MINUTE
             );
 
             const lerpDate = new Date(
-                lerp(
-                    data[Math.floor(fltIdx)].__timestamp,
-                    data[Math.ceil(fltIdx)].__timestamp,
-                    lerpAmt
-                )
+                lerp(binEdges[idx], binEdges[nextIdx], lerpAmt)
             );
 
             tooltipArtist.text(
-                `${key} : ${(minsListened / 60).toFixed(2)} hours listened`
+                `${keys()[keyIdx].key} : ${(minsListened / 60).toFixed(
+                    2
+                )} hours listened`
             );
             tooltipDate.text(
                 `Around ${lerpDate.toLocaleString('default', {
@@ -371,27 +245,25 @@ export function drawGraph(allTracks: TrackData[], allExtTracks: TrackData[]) {
     };
 
     const createSelectionOverlay = (
-        key: string,
+        keyIdx: number,
         mouseX: number,
         curTransform: d3.ZoomTransform
     ) => {
         canvas.style('opacity', 0.5);
         tooltip.attr('visibility', 'visible');
         tooltipBg.attr('visibility', 'visible');
-        updateSelectionOverlay(key, mouseX, curTransform);
-        const datum = dataLookup.get(key);
+        updateSelectionOverlay(keyIdx, mouseX, curTransform);
+        const path = outline(keyIdx);
         selectionGroup
             .append('path')
-            .data([datum])
-            .style('fill', (d) => String(color(d.key)))
-            .attr('d', area.context(null))
+            .style('fill', keys()[keyIdx].color)
+            .attr('d', path)
             .attr('stroke-width', SELECTION_STROKE_W / curTransform.k)
             .attr('transform', <any>curTransform);
         lineMask
             .append('path')
-            .data([datum])
             .attr('fill', 'white')
-            .attr('d', area.context(null))
+            .attr('d', path)
             .attr('stroke-width', SELECTION_STROKE_W / curTransform.k);
     };
 
@@ -424,7 +296,7 @@ export function drawGraph(allTracks: TrackData[], allExtTracks: TrackData[]) {
             offscreenCtx.draw();
         });
 
-    let selectedKey: string | null = null;
+    let selectedKeyIdx: number | null = null;
     let [zoomRectInitX, zoomRectInitY] = [0, 0];
     overlay
         .on('mousedown touchstart', (ev) => {
@@ -445,10 +317,10 @@ export function drawGraph(allTracks: TrackData[], allExtTracks: TrackData[]) {
                     .attr('y', Math.min(zoomRectInitY, mouseY))
                     .attr('width', Math.abs(zoomRectInitX - mouseX))
                     .attr('height', Math.abs(zoomRectInitY - mouseY));
-            } else if (selectedKey != null) {
+            } else if (selectedKeyIdx != null) {
                 const curTransform = d3.zoomTransform(canvas.node() as Element);
                 const mouseX = d3.pointers(ev, overlay.node())[0][0];
-                updateSelectionOverlay(selectedKey, mouseX, curTransform);
+                updateSelectionOverlay(selectedKeyIdx, mouseX, curTransform);
             }
         })
         .on('mouseup touchend', (ev) => {
@@ -473,21 +345,22 @@ export function drawGraph(allTracks: TrackData[], allExtTracks: TrackData[]) {
             // on a click
             if (zoomWidth == 0 || zoomHeight == 0) {
                 removeSelectionOverlay();
-                const keyFromColor = colorIdToKeyMap.get(
-                    color2hex(
-                        unpackRgba(
-                            offscreenCtx.get_pixel(
-                                Math.round(mouseX),
-                                Math.round(mouseY)
-                            )
-                        ).slice(0, 3)
-                    )
+                const pickColor = color2hex(
+                    unpackRgba(
+                        offscreenCtx.get_pixel(
+                            Math.round(mouseX),
+                            Math.round(mouseY)
+                        )
+                    ).slice(0, 3)
                 );
-                if (keyFromColor != undefined) {
-                    createSelectionOverlay(keyFromColor, mouseX, curTransform);
-                    selectedKey = keyFromColor;
+                const keyIdx = keys().findIndex(
+                    (info) => info.pickColor == pickColor
+                );
+                if (keyIdx != -1) {
+                    createSelectionOverlay(keyIdx, mouseX, curTransform);
+                    selectedKeyIdx = keyIdx;
                 } else {
-                    selectedKey = null;
+                    selectedKeyIdx = null;
                 }
                 return;
             }
@@ -534,12 +407,38 @@ export function drawGraph(allTracks: TrackData[], allExtTracks: TrackData[]) {
             }
         })
         .on('mouseout', () => {
-            tooltipArtist.text(selectedKey);
+            tooltipArtist.text(
+                selectedKeyIdx != null ? keys()[selectedKeyIdx].key : null
+            );
             tooltipDate.text('');
             mouseOverLine.attr('visibility', 'hidden');
             updateTooltipBg();
         });
 
-    // draws initial graph, redrawn on every zoom event
-    canvas.call(<any>zoom.transform, d3.zoomIdentity);
+    // the selection is dropped, series can move when records are added
+    const refresh = () => {
+        removeSelectionOverlay();
+        selectedKeyIdx = null;
+        const binEdges = dataset.bin_edges();
+        if (binEdges.length > 1) {
+            x.domain([
+                new Date(binEdges[0]),
+                new Date(binEdges[binEdges.length - 2])
+            ]);
+        }
+        // redraws both canvases and the axis at the current zoom
+        canvas.call(
+            <any>zoom.transform,
+            d3.zoomTransform(canvas.node() as Element)
+        );
+    };
+
+    return {
+        dataset,
+        canvasCtx,
+        offscreenCtx,
+        objects,
+        pickingObjects,
+        refresh
+    };
 }